@@ -0,0 +1,129 @@
+/*!
+ * Util bundles, distributed as .tar.gz, .tar.zst or .zip archives with a charon file at their root.
+ * Archives are unpacked using tar and unzip.
+ */
+
+use std::{env, fs::{self, DirBuilder}, io, os::unix::fs::DirBuilderExt, path::{Path, PathBuf}, process::Command, time::{SystemTime, UNIX_EPOCH}};
+use mythos_core::{printinfo, printwarn};
+use crate::auto_installer::CharonIoError;
+
+/**
+ * A directory that is removed once dropped.
+ */
+#[derive(Debug)]
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Result<TempDir, CharonIoError> {
+        //! Only the current user can access the directory.
+        //! Never reuses an existing path, since anyone could have created it in /tmp.
+        for attempt in 0..100 {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos());
+            let path = env::temp_dir().join(format!("{prefix}-{}-{nanos}-{attempt}", std::process::id()));
+            match DirBuilder::new().mode(0o700).create(&path) {
+                Ok(_) => return Ok(TempDir { path }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(CharonIoError::GenericIoError(err))
+            }
+        }
+        let err = io::Error::new(io::ErrorKind::AlreadyExists, "Could not find an unused temporary directory");
+        return Err(CharonIoError::GenericIoError(err));
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            printwarn!("Could not remove temporary directory {:?}. Error = {err}.", self.path);
+        }
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    return path.is_file() && [".tar.gz", ".tgz", ".tar.zst", ".tzst", ".zip"].iter().any(|ext| name.ends_with(ext));
+}
+
+pub fn unpack_temp(archive: &Path) -> Result<TempDir, CharonIoError> {
+    //! The archive's contents are removed once the returned TempDir is dropped.
+    let dir = TempDir::new("charon-archive")?;
+    unpack(archive, &dir.path)?;
+    return Ok(dir);
+}
+
+pub fn unpack(archive: &Path, dest: &Path) -> Result<(), CharonIoError> {
+    printinfo!("Unpacking {archive:?} into {dest:?}...");
+    let name = archive.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let mut cmd = if name.ends_with(".zip") {
+        let mut cmd = Command::new("unzip");
+        cmd.arg("-q").arg("-o").arg(archive).arg("-d").arg(dest);
+        cmd
+    } else {
+        let mut cmd = Command::new("tar");
+        if name.ends_with(".zst") || name.ends_with(".tzst") {
+            cmd.arg("--zstd");
+        } else {
+            cmd.arg("--gzip");
+        }
+        cmd.arg("-xf").arg(archive).arg("-C").arg(dest);
+        cmd
+    };
+
+    let output = cmd.output()?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(CharonIoError::ArchiveFailed(archive.to_path_buf(), msg));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+
+    fn pack(dir: &TempDir, name: &str, args: &[&str]) -> PathBuf {
+        let archive = dir.path.join(name);
+        let status = Command::new(args[0])
+            .args(&args[1..])
+            .arg(&archive)
+            .arg(".")
+            .current_dir("tests/archive/bundle")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        return archive;
+    }
+
+    #[test]
+    fn unpack_archives() {
+        let dir = TempDir::new("charon-archive-test").unwrap();
+        let archives = [
+            pack(&dir, "archive.tar.gz", &["tar", "--gzip", "-cf"]),
+            pack(&dir, "archive.tar.zst", &["tar", "--zstd", "-cf"]),
+            pack(&dir, "archive.zip", &["zip", "-q", "-r"]),
+        ];
+
+        for archive in archives {
+            assert!(is_archive(&archive));
+            let unpacked = unpack_temp(&archive).unwrap();
+            let path = unpacked.path.to_owned();
+            assert!(path.join("archive.charon").is_file());
+            assert!(path.join("targets/a.txt").is_file());
+
+            drop(unpacked);
+            assert!(!path.exists());
+        }
+        assert!(!is_archive(Path::new("tests/archive/bundle")));
+    }
+
+    #[test]
+    fn private_temp_dir() {
+        let first = TempDir::new("charon-temp-test").unwrap();
+        let second = TempDir::new("charon-temp-test").unwrap();
+        assert_ne!(first.path, second.path);
+        assert_eq!(fs::metadata(&first.path).unwrap().permissions().mode() & 0o777, 0o700);
+    }
+}
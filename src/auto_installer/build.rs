@@ -0,0 +1,76 @@
+use std::{collections::BTreeMap, path::PathBuf, process::Command};
+
+use mythos_core::printinfo;
+use toml::Value;
+
+use super::{BuildStep, CharonIoError};
+
+impl BuildStep {
+    pub fn parse(val: &Value, parent: &PathBuf) -> Result<BuildStep, CharonIoError> {
+        //! cwd is relative to the charon file, and defaults to the directory containing it.
+        let invalid = || CharonIoError::InvalidCharonFile(format!("Invalid build section: {val}"));
+        let command = match val.get("command") {
+            Some(Value::String(command)) => command.to_string(),
+            _ => return Err(invalid())
+        };
+        let cwd = match val.get("cwd") {
+            Some(Value::String(cwd)) => parent.join(cwd),
+            Some(_) => return Err(invalid()),
+            None => parent.to_owned()
+        };
+
+        let mut env = BTreeMap::new();
+        match val.get("env") {
+            Some(Value::Table(table)) => {
+                for (key, val) in table {
+                    match val {
+                        Value::String(val) => env.insert(key.to_string(), val.to_string()),
+                        _ => return Err(invalid())
+                    };
+                }
+            },
+            Some(_) => return Err(invalid()),
+            None => ()
+        }
+        return Ok(BuildStep { command, cwd, env });
+    }
+
+    pub fn detect(parent: &PathBuf) -> Option<BuildStep> {
+        //! Rust projects are built with cargo, unless the charon file has a build section.
+        if !parent.join("Cargo.toml").is_file() {
+            return None;
+        }
+        return Some(BuildStep { command: "cargo build".into(), cwd: parent.to_owned(), env: BTreeMap::new() });
+    }
+
+    pub fn run_for(val: Option<&Value>, parent: &PathBuf, do_dry_run: bool) -> Result<(), CharonIoError> {
+        //! Runs the build section if there is one, otherwise whatever detect finds.
+        let step = match val {
+            Some(val) => Some(BuildStep::parse(val, parent)?),
+            None => BuildStep::detect(parent)
+        };
+        return match step {
+            Some(step) => step.run(do_dry_run),
+            None => Ok(())
+        };
+    }
+
+    pub fn run(&self, do_dry_run: bool) -> Result<(), CharonIoError> {
+        //! Build commands may write anywhere, so dry runs only print them.
+        if do_dry_run {
+            printinfo!("\nDry run: Would have run build: {} (in {:?})", self.command, self.cwd);
+            return Ok(());
+        }
+        printinfo!("\nBuilding: {} (in {:?})", self.command, self.cwd);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(&self.cwd)
+            .envs(&self.env)
+            .status()?;
+        if !status.success() {
+            return Err(CharonIoError::BuildFailed(self.command.to_string(), status.to_string()));
+        }
+        return Ok(());
+    }
+}
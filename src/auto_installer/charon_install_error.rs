@@ -9,7 +9,19 @@ impl Display for CharonInstallError {
             CharonInstallError::GenericIoError(err) => write!(f, "An error occurred while copying file. Error = {err}."),
             CharonInstallError::DryRun => write!(f, "Installation is dryrun, no changes were made."),
             CharonInstallError::BadPermissions(err) => write!(f, "An error occurred while changing file permissions. Error = {err}."),
+            CharonInstallError::BadOwnership(err) => write!(f, "An error occurred while changing file ownership. Error = {err}."),
             CharonInstallError::FileExistsNoOverwrite => write!(f, "File exists and !overwrite."),
         };
     }
 }
+
+impl CharonInstallError {
+    pub fn is_fatal(&self) -> bool {
+        //! Fatal errors abort the whole installation.
+        //! DryRun and FileExistsNoOverwrite are expected outcomes.
+        return matches!(self, 
+            CharonInstallError::GenericIoError(_) 
+            | CharonInstallError::BadPermissions(_) 
+            | CharonInstallError::BadOwnership(_));
+    }
+}
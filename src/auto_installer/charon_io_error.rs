@@ -18,6 +18,29 @@ impl Display for CharonIoError {
             CharonIoError::UnknownUtilName(Some(util)) => write!(f, "Unknown util {util}."),
             CharonIoError::UnknownUtilName(None) => write!(f, "Could not obtain util name from either charon file or $CWD."),
             CharonIoError::InfoSourceBad(path) => write!(f, "Tried to interpret source path provided in info field as relative path, but canonicalization failed. SourcePath = {path:?}."),
+            CharonIoError::InstallFailed(path, err) => write!(f, "Could not install {path:?}, installation was rolled back. {err}"),
+            CharonIoError::GlobMatchedNothing(pattern, i) => write!(f, "Glob pattern \"{pattern}\" on line {i} did not match any files."),
+            CharonIoError::AmbiguousAlias(alias, i) => write!(f, "Alias \"{alias}\" on line {i} would be used for several files. Use {{name}}, {{stem}} or {{ext}} to give each file its own name."),
+            CharonIoError::UndefinedTemplateVar(var, path, i) => write!(f, "Undefined variable \"{var}\" on line {i} of template {path:?}."),
+            CharonIoError::NoSourcePath(util) => write!(f, "No source path found for {util} in the main index."),
+            CharonIoError::InvalidVersionReq(req) => write!(f, "Invalid version requirement \"{req}\". Expected something like \"^1.2\", \">=1.0, <2.0\" or \"=1.2.3\"."),
+            CharonIoError::NoVersionToPin(util) => write!(f, "{util} has no version in the main index. Pin it to a version requirement instead, e.g. --pin {util}=^1.0."),
+            CharonIoError::GitFailed(cmd, msg) => write!(f, "git {cmd} failed. {msg}"),
+            CharonIoError::ArchiveFailed(path, msg) => write!(f, "Could not unpack archive {path:?}. {msg}"),
+            CharonIoError::TargetOutsideBundle(path) => write!(f, "Cannot pack {path:?}, targets must be inside the directory containing the charon file."),
+            CharonIoError::HookFailed(hook, status) => write!(f, "{hook} hook failed. {status}."),
+            CharonIoError::BuildFailed(cmd, status) => write!(f, "Build command \"{cmd}\" failed. {status}."),
+            CharonIoError::InvalidDependency(dep) => write!(f, "Invalid dependency \"{dep}\". Expected a util name, optionally followed by a version requirement, e.g. \"util>=1.2\"."),
+            CharonIoError::UnmetDependencies(util, deps) => write!(f, "{util} requires {}. Install them first, or use --with-deps.", deps.join(", ")),
+            CharonIoError::RequiredBy(util, dependents) => write!(f, "{util} is required by {}. Use --cascade to uninstall them as well.", dependents.join(", ")),
+            CharonIoError::DependencyCycle(utils) => write!(f, "Dependency cycle: {}.", utils.join(" -> ")),
+            CharonIoError::InvalidUtilName(name) => write!(f, "Invalid util name \"{name}\". Util names cannot be empty or \"index\", and cannot contain whitespace or path separators."),
+            CharonIoError::FileConflict(conflicts) => {
+                let files: Vec<String> = conflicts.iter().map(|(dest, owner)| format!("{dest:?} (installed by {owner})")).collect();
+                write!(f, "Files were installed by other utils: {}. Use --take-ownership to install them anyway.", files.join(", "))
+            },
+            CharonIoError::SourceVersionMismatch(util, installed, source) => write!(f, "{util} v{installed} is installed, but its source is at v{source}. Update it instead, or use --force to repair it from the current source."),
+            CharonIoError::InvalidPerms(perms, i) => write!(f, "Invalid perms on line {i}: \"{perms}\". Expected an octal string like \"755\" or a symbolic mode like \"u+x\"."),
         };
     }
 }
@@ -0,0 +1,204 @@
+use mythos_core::printwarn;
+use toml::Value;
+
+use super::{CharonIoError, FileMode, ModeClause};
+
+const USER: u8 = 1;
+const GROUP: u8 = 2;
+const OTHER: u8 = 4;
+
+impl FileMode {
+    pub fn parse(val: &Value, line_num: usize) -> Result<FileMode, CharonIoError> {
+        //! Accepts octal strings ("755", "0o755") and symbolic modes ("u+x", "go-w,a+r").
+        //! Integers are rejected, since toml does not keep the base they were written in (0o644 == 644 == 0x284).
+        //! The one exception are the hex literals older charon files used (0x755), which are reinterpreted as octal with a warning.
+        return match val {
+            Value::Integer(val) => parse_integer(*val, line_num),
+            Value::String(val) => parse_string(val, line_num),
+            _ => Err(CharonIoError::InvalidPerms(val.to_string(), line_num)),
+        };
+    }
+
+    pub fn apply(&self, current: u32) -> u32 {
+        //! Returns the mode a file with permissions `current` should end up with.
+        return match self {
+            FileMode::Octal(mode) => *mode,
+            FileMode::Symbolic(clauses) => clauses.iter().fold(current, |mode, clause| clause.apply(mode)),
+        };
+    }
+}
+
+impl ModeClause {
+    fn apply(&self, mode: u32) -> u32 {
+        let mut bits = 0;
+        // Bits cleared by '='.
+        let mut mask = 0;
+
+        for (who, shift, special) in [(USER, 6, 0o4000), (GROUP, 3, 0o2000), (OTHER, 0, 0o1000)] {
+            if self.who & who == 0 {
+                continue;
+            }
+            mask |= (0o7 << shift) | special;
+
+            for c in self.perms.chars() {
+                bits |= match c {
+                    'r' => 0o4 << shift,
+                    'w' => 0o2 << shift,
+                    'x' => 0o1 << shift,
+                    // Only executable if someone can already execute it.
+                    'X' if mode & 0o111 != 0 => 0o1 << shift,
+                    's' if who != OTHER => special,
+                    't' if who == OTHER => special,
+                    _ => 0,
+                };
+            }
+        }
+
+        return match self.op {
+            '+' => mode | bits,
+            '-' => mode & !bits,
+            _ => (mode & !mask) | bits,
+        };
+    }
+}
+
+fn parse_integer(val: i64, line_num: usize) -> Result<FileMode, CharonIoError> {
+    if val < 0 {
+        return Err(CharonIoError::InvalidPerms(val.to_string(), line_num));
+    }
+    let val = val as u32;
+    let hex = format!("{val:x}");
+
+    // 0x755 == 1877, which is never a sensible mode.
+    // If the hex digits read as an octal mode, assume that is what was meant.
+    if val > 0o777 && hex.len() <= 4 && hex.chars().all(|c| ('0'..='7').contains(&c)) {
+        let mode = u32::from_str_radix(&hex, 8).unwrap_or(0);
+        printwarn!("perms = 0x{hex} on line {line_num} is a hex value. Interpreting it as octal {mode:o}. Write perms = \"{mode:o}\" instead.");
+        return Ok(FileMode::Octal(mode));
+    }
+    return Err(CharonIoError::InvalidPerms(val.to_string(), line_num));
+}
+
+fn parse_string(val: &str, line_num: usize) -> Result<FileMode, CharonIoError> {
+    let val = val.trim();
+    let octal = val.strip_prefix("0o").unwrap_or(val);
+
+    if !octal.is_empty() && octal.chars().all(|c| c.is_ascii_digit()) {
+        return match u32::from_str_radix(octal, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(FileMode::Octal(mode)),
+            _ => Err(CharonIoError::InvalidPerms(val.to_string(), line_num)),
+        };
+    }
+
+    return match parse_symbolic(val) {
+        Some(clauses) => Ok(FileMode::Symbolic(clauses)),
+        None => Err(CharonIoError::InvalidPerms(val.to_string(), line_num)),
+    };
+}
+
+fn parse_symbolic(val: &str) -> Option<Vec<ModeClause>> {
+    //! Same syntax as chmod: [ugoa]*[+-=][rwxXst]*, separated by commas.
+    //! Several operations may follow one another, e.g. "u+x-w".
+    let mut clauses = Vec::new();
+
+    for part in val.split(',') {
+        let mut chars = part.chars().peekable();
+
+        let mut who = 0;
+        while let Some(&c) = chars.peek() {
+            who |= match c {
+                'u' => USER,
+                'g' => GROUP,
+                'o' => OTHER,
+                'a' => USER | GROUP | OTHER,
+                _ => break,
+            };
+            chars.next();
+        }
+        if who == 0 {
+            who = USER | GROUP | OTHER;
+        }
+
+        let mut op = chars.next()?;
+        if !"+-=".contains(op) {
+            return None;
+        }
+
+        loop {
+            let mut perms = String::new();
+            let mut next_op = None;
+            for c in chars.by_ref() {
+                if "+-=".contains(c) {
+                    next_op = Some(c);
+                    break;
+                }
+                if !"rwxXst".contains(c) {
+                    return None;
+                }
+                perms.push(c);
+            }
+
+            clauses.push(ModeClause { who, op, perms });
+            match next_op {
+                Some(c) => op = c,
+                None => break,
+            }
+        }
+    }
+    return Some(clauses);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(val: Value) -> Result<FileMode, CharonIoError> {
+        return FileMode::parse(&val, 0);
+    }
+
+    #[test]
+    fn parse_octal() {
+        assert_eq!(parse(Value::String("755".into())).unwrap(), FileMode::Octal(0o755));
+        assert_eq!(parse(Value::String("0o644".into())).unwrap(), FileMode::Octal(0o644));
+        assert_eq!(parse(Value::String("4755".into())).unwrap(), FileMode::Octal(0o4755));
+    }
+
+    #[test]
+    fn reject_integers() {
+        // Toml octal literals arrive as their decimal value, 0o644 == 420 must not become 0o420.
+        for val in [0o644, 0o444, 0o640, 0o664, 0o711, 0o755, 0o600, 0o700, 644, 755, 0] {
+            assert!(matches!(parse(Value::Integer(val)), Err(CharonIoError::InvalidPerms(_, _))), "{val:o}");
+        }
+    }
+
+    #[test]
+    fn parse_hex_as_octal() {
+        assert_eq!(parse(Value::Integer(0x755)).unwrap(), FileMode::Octal(0o755));
+        assert_eq!(parse(Value::Integer(0x644)).unwrap(), FileMode::Octal(0o644));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!(parse(Value::String("0x755".into())), Err(CharonIoError::InvalidPerms(_, _))));
+        assert!(matches!(parse(Value::String("789".into())), Err(CharonIoError::InvalidPerms(_, _))));
+        assert!(matches!(parse(Value::String("u+q".into())), Err(CharonIoError::InvalidPerms(_, _))));
+        assert!(matches!(parse(Value::String("u+x,".into())), Err(CharonIoError::InvalidPerms(_, _))));
+        assert!(matches!(parse(Value::Integer(0x1ffff)), Err(CharonIoError::InvalidPerms(_, _))));
+        assert!(matches!(parse(Value::Integer(-1)), Err(CharonIoError::InvalidPerms(_, _))));
+        assert!(matches!(parse(Value::Boolean(true)), Err(CharonIoError::InvalidPerms(_, _))));
+    }
+
+    #[test]
+    fn apply_symbolic() {
+        let apply = |mode: &str, current: u32| parse(Value::String(mode.into())).unwrap().apply(current);
+
+        assert_eq!(apply("u+x", 0o644), 0o744);
+        assert_eq!(apply("+x", 0o644), 0o755);
+        assert_eq!(apply("go-w", 0o666), 0o644);
+        assert_eq!(apply("u=rwx,go=rx", 0o600), 0o755);
+        assert_eq!(apply("u+x-w", 0o644), 0o544);
+        assert_eq!(apply("a+X", 0o644), 0o644);
+        assert_eq!(apply("a+X", 0o744), 0o755);
+        assert_eq!(apply("u+s,+t", 0o755), 0o5755);
+    }
+}
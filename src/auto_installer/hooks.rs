@@ -0,0 +1,157 @@
+use std::{path::PathBuf, process::Command};
+
+use mythos_core::{dirs::{get_path, MythosDir}, printinfo, printwarn};
+use toml::{map::Map, Value};
+
+use super::{installation_cmd::mythos_dir_vars, CharonIoError, Hooks};
+
+const HOOK_NAMES: [&str; 5] = ["pre_install", "post_install", "pre_uninstall", "post_uninstall", "post_update"];
+
+impl Hooks {
+    pub fn parse(val: &Value, line_num: usize) -> Result<Hooks, CharonIoError> {
+        //! Every hook is a single shell command, run using sh -c.
+        let table = match val {
+            Value::Table(table) => table,
+            _ => return Err(CharonIoError::InvalidInstallItem(val.to_string(), line_num))
+        };
+
+        let mut hooks = Hooks::default();
+        for (key, val) in table {
+            let cmd = match val {
+                Value::String(cmd) => cmd.to_string(),
+                _ => return Err(CharonIoError::InvalidInstallItem(format!("{key} = {val}"), line_num))
+            };
+            match hooks.get_mut(key) {
+                Some(hook) => *hook = Some(cmd),
+                None => return Err(CharonIoError::InvalidInstallItem(format!("Unknown hook {key}"), line_num))
+            }
+        }
+        return Ok(hooks);
+    }
+
+    pub fn get(&self, hook: &str) -> Option<&String> {
+        return match hook {
+            "pre_install" => self.pre_install.as_ref(),
+            "post_install" => self.post_install.as_ref(),
+            "pre_uninstall" => self.pre_uninstall.as_ref(),
+            "post_uninstall" => self.post_uninstall.as_ref(),
+            "post_update" => self.post_update.as_ref(),
+            _ => None,
+        };
+    }
+
+    fn get_mut(&mut self, hook: &str) -> Option<&mut Option<String>> {
+        return match hook {
+            "pre_install" => Some(&mut self.pre_install),
+            "post_install" => Some(&mut self.post_install),
+            "pre_uninstall" => Some(&mut self.pre_uninstall),
+            "post_uninstall" => Some(&mut self.post_uninstall),
+            "post_update" => Some(&mut self.post_update),
+            _ => None,
+        };
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut table = Map::new();
+        for hook in HOOK_NAMES {
+            if let Some(cmd) = self.get(hook) {
+                table.insert(hook.into(), Value::String(cmd.to_string()));
+            }
+        }
+        return Value::Table(table);
+    }
+
+    pub fn from_value(val: &Value) -> Hooks {
+        //! Used to read hooks back from a util index. Unknown keys are ignored.
+        //! Older indexes saved cwd, which may have been a temporary directory, so it is ignored as well.
+        let mut hooks = Hooks::default();
+        for hook in HOOK_NAMES {
+            if let Some(Value::String(cmd)) = val.get(hook) {
+                *hooks.get_mut(hook).unwrap() = Some(cmd.to_string());
+            }
+        }
+        return hooks;
+    }
+
+    pub fn run(&self, hook: &str, util_name: &str, version: Option<&String>, do_dry_run: bool) -> Result<(), CharonIoError> {
+        //! Does nothing if the hook was not declared.
+        //! The hook gets CHARON_UTIL_NAME, CHARON_UTIL_VERSION and every MYTHOS_*_DIR as environment variables.
+        let cmd = match self.get(hook) {
+            Some(cmd) => cmd,
+            None => return Ok(())
+        };
+        if do_dry_run {
+            printinfo!("Dry run: Would have run {hook} hook: {cmd}");
+            return Ok(());
+        }
+        printinfo!("Running {hook} hook: {cmd}");
+
+        let mut env = mythos_dir_vars();
+        env.insert("CHARON_UTIL_NAME".into(), util_name.to_string());
+        env.insert("CHARON_UTIL_VERSION".into(), version.cloned().unwrap_or_default());
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(cmd).envs(&env).current_dir(self.get_cwd());
+
+        let status = command.status()?;
+        if !status.success() {
+            return Err(CharonIoError::HookFailed(hook.to_string(), status.to_string()));
+        }
+        return Ok(());
+    }
+
+    fn get_cwd(&self) -> PathBuf {
+        let default = get_path(MythosDir::Data, "charon").filter(|path| path.is_dir()).unwrap_or(PathBuf::from("/"));
+        return match &self.cwd {
+            Some(cwd) if cwd.is_dir() => cwd.to_owned(),
+            Some(cwd) => {
+                printwarn!("Hook directory {cwd:?} does not exist. Running hooks from {default:?} instead.");
+                default
+            },
+            None => default
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use serial_test::serial;
+    use super::*;
+
+    #[serial]
+    #[test]
+    fn run_hooks() {
+        let val: Value = toml::from_str("pre_install = \"test \\\"$CHARON_UTIL_NAME\\\" = hooked\"\npost_install = \"exit 3\"").unwrap();
+        let hooks = Hooks::parse(&val, 0).unwrap();
+
+        hooks.run("pre_install", "hooked", None, false).unwrap();
+        hooks.run("post_update", "hooked", None, false).unwrap();
+        assert!(matches!(hooks.run("post_install", "hooked", None, false), Err(CharonIoError::HookFailed(_, _))));
+        hooks.run("post_install", "hooked", None, true).unwrap();
+        assert!(matches!(hooks.run("pre_install", "other", None, false), Err(CharonIoError::HookFailed(_, _))));
+
+        assert_eq!(Hooks::from_value(&hooks.to_value()), hooks);
+    }
+
+    #[serial]
+    #[test]
+    fn default_cwd() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_renamed");
+        }
+        let mut hooks = Hooks { post_uninstall: Some("test -f index.charon".into()), ..Hooks::default() };
+        hooks.run("post_uninstall", "hooked", None, false).unwrap();
+
+        hooks.cwd = Some(PathBuf::from("tests/hooks/missing"));
+        hooks.run("post_uninstall", "hooked", None, false).unwrap();
+        assert_eq!(hooks.to_value().get("cwd"), None);
+        assert_eq!(Hooks::from_value(&hooks.to_value()).cwd, None);
+    }
+
+    #[test]
+    fn unknown_hook() {
+        let val: Value = toml::from_str("pre_instal = \"true\"").unwrap();
+        assert!(matches!(Hooks::parse(&val, 3), Err(CharonIoError::InvalidInstallItem(_, 3))));
+    }
+}
@@ -1,23 +1,30 @@
-use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+use std::{fs, io, os::unix::fs::{chown, MetadataExt, PermissionsExt}, path::{Path, PathBuf}};
 
-use super::{CharonInstallError, InstallItem};
+use crate::util_index;
+
+use super::{CharonInstallError, InstallItem, Transaction};
 
 impl InstallItem {
     pub fn new() -> InstallItem {
         return InstallItem {
             target: PathBuf::new(),
             dest: PathBuf::new(),
-            perms: 0,
+            perms: None,
+            owner: None,
+            group: None,
             strip_ext: false,
             overwrite: true,
+            link: false,
+            template: false,
+            contents: None,
+            force: false,
             comment: "".into(),
         };
     }
-    pub fn print_dest(&self) -> String {
-        return self.dest.to_string_lossy().to_string();
-    }
 
-    pub fn try_install(&mut self, do_dry_run: bool) -> Result<(), CharonInstallError> {
+    pub fn try_install(&mut self, transaction: &mut Transaction, do_dry_run: bool) -> Result<(), CharonInstallError> {
+        //! Files are copied (or linked) next to dest, then renamed into place.
+        //! Any file that gets overwritten is backed up by the transaction.
         // GenericIoError >> BadPermissions >> NoOverwrite >> DryRun
         let mut comment = vec!["#".to_string()];
         // Init error code.
@@ -36,22 +43,37 @@ impl InstallItem {
             comment.push("File exists && !overwrite".into());
             err = Some(CharonInstallError::FileExistsNoOverwrite);
 
+        } else if !self.force && self.is_up_to_date() {
+            comment.push("Unchanged".into());
+
         } else if !do_dry_run {
-            match fs::copy(&self.target, &self.dest) {
-                Ok(_) => {
-                    comment.push("Successfully installed".into());
-                    match self.dest.metadata() {
-                        Ok(metadata) => metadata.permissions().set_mode(self.perms),
-                        Err(msg) => {
-                            comment.push(format!("Error changing permissions for {:?}. {msg}.", self.dest));
-
-                            err = Some(CharonInstallError::BadPermissions(msg));
-                        }
-                    }
+            match self.install_staged(transaction) {
+                Ok(_) => comment.push("Successfully installed".into()),
+                Err(CharonInstallError::BadPermissions(msg)) => {
+                    comment.push(format!("Error changing permissions for {:?}. {msg}.", self.dest));
+                    err = Some(CharonInstallError::BadPermissions(msg));
                 },
-                Err(msg) => {
-                    comment.push(format!("Could not copy file: {msg}"));
-                    err = Some(CharonInstallError::GenericIoError(msg));
+                Err(CharonInstallError::BadOwnership(msg)) => {
+                    comment.push(format!("Error changing ownership for {:?}. {msg}.", self.dest));
+                    err = Some(CharonInstallError::BadOwnership(msg));
+                },
+                Err(other) => {
+                    comment.push(format!("Could not copy file: {other}"));
+                    err = Some(other);
+                }
+            }
+        } else if self.link {
+            comment.push(format!("Would link to {:?}", self.target));
+        } else {
+            if let Some(mode) = self.expected_mode() {
+                comment.push(format!("Permissions would be set to {mode:o}"));
+            }
+            if self.owner.is_some() || self.group.is_some() {
+                match self.resolve_ownership() {
+                    Ok(_) => comment.push(format!("Ownership would be set to {}:{}", 
+                            self.owner.as_deref().unwrap_or(""), 
+                            self.group.as_deref().unwrap_or(""))),
+                    Err(msg) => comment.push(format!("Ownership could not be set. {msg}.")),
                 }
             }
         }
@@ -63,5 +85,212 @@ impl InstallItem {
         }
         return Ok(());
     }
+
+    pub fn checksum(&self) -> Option<String> {
+        //! Checksum of what would be installed to dest.
+        return match &self.contents {
+            Some(contents) => Some(util_index::checksum(contents.as_bytes())),
+            None => util_index::file_checksum(&self.target).ok(),
+        };
+    }
+
+    pub fn expected_mode(&self) -> Option<u32> {
+        //! Permissions dest will have once installed, based on the target's current permissions.
+        let current = fs::metadata(&self.target).ok()?.permissions().mode() & 0o7777;
+        return match &self.perms {
+            Some(mode) => Some(mode.apply(current)),
+            None => Some(current),
+        };
+    }
+
+    fn is_up_to_date(&self) -> bool {
+        //! Dest already matches what would be installed.
+        //! Compares size and mtime first, contents are only read when the mtimes differ.
+        let dest = match fs::symlink_metadata(&self.dest) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        if self.link {
+            return dest.file_type().is_symlink() 
+                && fs::read_link(&self.dest).ok() == fs::canonicalize(&self.target).ok();
+        }
+        if !dest.file_type().is_file() 
+            || Some(dest.permissions().mode() & 0o7777) != self.expected_mode() 
+            || !self.has_expected_ownership(&dest) {
+            return false;
+        }
+
+        if let Some(contents) = &self.contents {
+            return dest.len() == contents.len() as u64 
+                && fs::read(&self.dest).is_ok_and(|bytes| bytes == contents.as_bytes());
+        }
+
+        let target = match fs::metadata(&self.target) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        if dest.len() != target.len() {
+            return false;
+        }
+        if dest.modified().is_ok_and(|time| target.modified().is_ok_and(|target| target == time)) {
+            return true;
+        }
+        return self.checksum().is_some() && self.checksum() == util_index::file_checksum(&self.dest).ok();
+    }
+
+    fn has_expected_ownership(&self, dest: &fs::Metadata) -> bool {
+        if self.owner.is_none() && self.group.is_none() {
+            return true;
+        }
+        return match self.resolve_ownership() {
+            Ok((uid, gid)) => uid.map_or(true, |uid| uid == dest.uid()) && gid.map_or(true, |gid| gid == dest.gid()),
+            Err(_) => false,
+        };
+    }
+
+    fn install_staged(&self, transaction: &mut Transaction) -> Result<(), CharonInstallError> {
+        //! Permissions are applied to the staged copy, so dest never exists with the wrong mode.
+        //! Links always have the target's permissions and ownership.
+        let staged = if self.link {
+            transaction.stage_link(&self.target, &self.dest)
+        } else if let Some(contents) = &self.contents {
+            transaction.stage_contents(&self.target, contents, &self.dest)
+        } else {
+            transaction.stage_copy(&self.target, &self.dest)
+        };
+        let staged = match staged {
+            Ok(staged) => staged,
+            Err(err) => return Err(CharonInstallError::GenericIoError(err)),
+        };
+
+        if self.link {
+            return match transaction.install_file(&staged, &self.dest) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(CharonInstallError::GenericIoError(err)),
+            };
+        }
+
+        // chown clears setuid and setgid, so ownership has to be changed first.
+        if let Err(err) = self.apply_ownership(&staged) {
+            let _ = fs::remove_file(&staged);
+            return Err(CharonInstallError::BadOwnership(err));
+        }
+
+        if let Err(err) = self.apply_perms(&staged) {
+            let _ = fs::remove_file(&staged);
+            return Err(CharonInstallError::BadPermissions(err));
+        }
+
+        if let Err(err) = transaction.install_file(&staged, &self.dest) {
+            return Err(CharonInstallError::GenericIoError(err));
+        }
+        return Ok(());
+    }
+
+    fn apply_perms(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mode = match &self.perms {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+        let current = fs::metadata(path)?.permissions().mode() & 0o7777;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode.apply(current)))?;
+        return Ok(());
+    }
+
+    fn apply_ownership(&self, path: &Path) -> Result<(), io::Error> {
+        if self.owner.is_none() && self.group.is_none() {
+            return Ok(());
+        }
+        let (uid, gid) = self.resolve_ownership()?;
+        return chown(path, uid, gid);
+    }
+
+    fn resolve_ownership(&self) -> Result<(Option<u32>, Option<u32>), io::Error> {
+        let uid = match &self.owner {
+            Some(owner) => Some(lookup_id("/etc/passwd", owner)?),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => Some(lookup_id("/etc/group", group)?),
+            None => None,
+        };
+        return Ok((uid, gid));
+    }
+}
+
+fn lookup_id(db: &str, name: &str) -> Result<u32, io::Error> {
+    //! Numeric ids are used as is. Names are looked up in /etc/passwd or /etc/group.
+    //! Both files have the form name:password:id:...
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+
+    let contents = fs::read_to_string(db)?;
+    for line in contents.lines() {
+        let mut fields = line.split(':');
+        if fields.next() != Some(name) {
+            continue;
+        }
+        if let Some(Ok(id)) = fields.nth(1).map(|id| id.parse::<u32>()) {
+            return Ok(id);
+        }
+    }
+    return Err(io::Error::new(io::ErrorKind::NotFound, format!("No entry for {name} in {db}")));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use serial_test::serial;
+    use crate::{archive::TempDir, auto_installer::{parse_installation_file, FileMode, InstallOpts}};
+    use super::*;
+
+    #[serial]
+    #[test]
+    fn skip_unchanged() {
+        unsafe {
+            env::set_var("MYTHOS_CONFIG_DIR", "tests/unchanged/dests/etc");
+        }
+        let mut cmd = parse_installation_file(&PathBuf::from("tests/unchanged/unchanged.charon"), &InstallOpts::default()).unwrap();
+
+        let item = &mut cmd.items[0];
+        let _ = item.try_install(&mut Transaction::new(), true);
+        assert!(item.comment.contains("Unchanged"));
+
+        let item = &mut cmd.items[1];
+        let _ = item.try_install(&mut Transaction::new(), true);
+        assert!(!item.comment.contains("Unchanged"));
+
+        let item = &mut cmd.items[0];
+        item.comment = String::new();
+        item.force = true;
+        let _ = item.try_install(&mut Transaction::new(), true);
+        assert!(!item.comment.contains("Unchanged"));
+    }
+
+    #[test]
+    fn setuid_survives_chown() {
+        let dir = TempDir::new("charon-ownership-test").unwrap();
+        let mut item = InstallItem::new();
+        item.target = PathBuf::from("tests/ownership/setuid.sh");
+        item.dest = dir.path.join("setuid.sh");
+        item.perms = Some(FileMode::Octal(0o4755));
+        item.owner = Some(fs::metadata(&item.target).unwrap().uid().to_string());
+
+        let mut transaction = Transaction::new();
+        item.try_install(&mut transaction, false).unwrap();
+        transaction.commit();
+
+        let mode = fs::metadata(&item.dest).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o4755);
+    }
+
+    #[test]
+    fn lookup_ids() {
+        assert_eq!(lookup_id("/etc/passwd", "1000").unwrap(), 1000);
+        assert_eq!(lookup_id("/etc/passwd", "root").unwrap(), 0);
+        assert_eq!(lookup_id("/etc/group", "root").unwrap(), 0);
+        assert!(lookup_id("/etc/passwd", "charon_user_dne").is_err());
+    }
 }
 
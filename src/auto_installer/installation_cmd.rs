@@ -1,11 +1,12 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
 
-use mythos_core::{dirs, printinfo};
-use toml::Value;
+use glob::{MatchOptions, Pattern};
+use mythos_core::{dirs::{self, MythosDir}, printinfo, printwarn};
+use toml::{map::Map, Value};
 
-use crate::auto_installer::InstallItem;
+use crate::{auto_installer::{template, FileMode, InstallItem}, dependencies::Dependency};
 
-use super::{CharonIoError, InstallationCmd};
+use super::{CharonIoError, Hooks, InstallationCmd};
 
 impl InstallationCmd {
     pub fn new() -> InstallationCmd {
@@ -14,8 +15,16 @@ impl InstallationCmd {
             mkdirs: Vec::new(),
             name: "".into(),
             source: None,
+            branch: None,
+            tag: None,
+            rev: None,
+            commit: None,
             version: None,
             description: None,
+            vars: BTreeMap::new(),
+            hooks: Hooks::default(),
+            depends: Vec::new(),
+            charon_file: None,
         };
     }
     pub fn set_info(&mut self, val: &Value, charon_path: &PathBuf) -> Result<(), CharonIoError> {
@@ -32,6 +41,79 @@ impl InstallationCmd {
         if let Some(Value::String(val)) = val.get("source") {
             self.source = Some(validate(val, &charon_path)?);
         }
+
+        match val.get("depends") {
+            Some(Value::Array(depends)) => {
+                for dep in depends {
+                    let parsed = dep.as_str().and_then(Dependency::parse);
+                    match parsed {
+                        Some(parsed) => self.depends.push(parsed),
+                        None => return Err(CharonIoError::InvalidDependency(dep.to_string()))
+                    }
+                }
+            },
+            Some(other) => return Err(CharonIoError::InvalidDependency(other.to_string())),
+            None => ()
+        }
+
+        let git_refs = [("branch", &mut self.branch), ("tag", &mut self.tag), ("rev", &mut self.rev)];
+        let mut count = 0;
+        for (key, field) in git_refs {
+            if let Some(Value::String(val)) = val.get(key) {
+                // Would be read as an option by git.
+                if val.starts_with('-') {
+                    return Err(CharonIoError::InvalidCharonFile(format!("Invalid {key} \"{val}\", git refs cannot start with '-'")));
+                }
+                *field = Some(val.to_string());
+                count += 1;
+            }
+        }
+        if count > 1 {
+            return Err(CharonIoError::InvalidCharonFile("Only one of branch, tag or rev may be set in the info section".into()));
+        }
+        return Ok(());
+    }
+    pub fn set_vars(&mut self, val: &Value, line_num: usize) -> Result<(), CharonIoError> {
+        //! Read the vars section of a .charon file.
+        let table = match val {
+            Value::Table(table) => table,
+            _ => return Err(CharonIoError::InvalidInstallItem(val.to_string(), line_num))
+        };
+        for (key, val) in table {
+            let val = match val {
+                Value::String(val) => val.to_string(),
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => val.to_string(),
+                _ => return Err(CharonIoError::InvalidInstallItem(val.to_string(), line_num))
+            };
+            self.vars.insert(key.to_string(), val);
+        }
+        return Ok(());
+    }
+    pub fn template_vars(&self) -> BTreeMap<String, String> {
+        //! Variables available to templates. Anything declared in the vars section takes precedence.
+        let mut vars = BTreeMap::new();
+        vars.insert("util_name".to_string(), self.name.to_string());
+        if let Some(version) = &self.version {
+            vars.insert("version".to_string(), version.to_string());
+        }
+        if let Some(description) = &self.description {
+            vars.insert("description".to_string(), description.to_string());
+        }
+
+        vars.extend(mythos_dir_vars());
+        vars.extend(self.vars.clone());
+        return vars;
+    }
+    pub fn render_templates(&mut self) -> Result<(), CharonIoError> {
+        //! Render every item with template = true.
+        let vars = self.template_vars();
+        for item in &mut self.items {
+            if !item.template {
+                continue;
+            }
+            let contents = fs::read_to_string(&item.target)?;
+            item.contents = Some(template::render(&contents, &vars, &item.target)?);
+        }
         return Ok(());
     }
     pub fn add_item(&mut self, parent: &PathBuf, dest: &PathBuf, val: &Value, line_num: usize) -> Result<(), CharonIoError>{
@@ -41,9 +123,15 @@ impl InstallationCmd {
         let mut cmd = InstallItem {
             target: parent.into(),
             dest: dest.into(),
-            perms: 000,
+            perms: None,
+            owner: None,
+            group: None,
             strip_ext: false,
             overwrite: true,
+            link: false,
+            template: false,
+            contents: None,
+            force: false,
             comment: "".into(),
         };
         let table = match val {
@@ -61,23 +149,31 @@ impl InstallationCmd {
         let mut dest = None;
         let mut alias: Option<PathBuf> = None;
 
-        // Resolve target path and ensure it exists.
-        if let Some(Value::String(val)) = table.get("target") {
-            // cmd.target = val.into();
-            cmd.target = parent.join(&val).canonicalize().unwrap_or(val.into());
-        } else {
-            return Err(CharonIoError::NoTargetProvided(line_num));
-        }
-        if !cmd.target.exists() {
-            return Err(CharonIoError::TargetFileNotFound(cmd.target.into(), line_num));
+        // Resolve target path(s) and ensure they exist.
+        // Glob patterns expand into one item per match, which share every other setting.
+        let targets = match table.get("target") {
+            Some(Value::String(val)) if is_glob(val) => expand_glob(parent, val, line_num)?,
+            Some(Value::String(val)) => vec![parent.join(&val).canonicalize().unwrap_or(val.into())],
+            _ => return Err(CharonIoError::NoTargetProvided(line_num)),
+        };
+        for target in &targets {
+            if !target.exists() {
+                return Err(CharonIoError::TargetFileNotFound(target.into(), line_num));
+            }
         }
 
 
         if let Some(Value::String(val)) = table.get("dest") {
             dest = Some(PathBuf::from(val));
         }
-        if let Some(Value::Integer(val)) = table.get("perms") {
-            cmd.perms = val.to_owned() as u32;
+        if let Some(val) = table.get("perms") {
+            cmd.perms = Some(FileMode::parse(val, line_num)?);
+        }
+        if let Some(val) = table.get("owner") {
+            cmd.owner = Some(parse_id(val, line_num)?);
+        }
+        if let Some(val) = table.get("group") {
+            cmd.group = Some(parse_id(val, line_num)?);
         }
         if let Some(Value::Boolean(val)) = table.get("strip_ext") {
             cmd.strip_ext = val.to_owned();
@@ -88,9 +184,55 @@ impl InstallationCmd {
         if let Some(Value::Boolean(val)) = table.get("overwrite") {
             cmd.overwrite = val.to_owned();
         }
+        if let Some(Value::Boolean(val)) = table.get("link") {
+            cmd.link = val.to_owned();
+        }
+        if let Some(Value::Boolean(val)) = table.get("template") {
+            cmd.template = val.to_owned();
+        }
+        if cmd.template && cmd.link {
+            let targets = targets.iter().map(|target| format!("{target:?}")).collect::<Vec<String>>().join(", ");
+            printwarn!("Templates cannot be linked, {targets} will be copied instead. Item declared on line {line_num}.");
+            cmd.link = false;
+        }
         if let Some(Value::String(val)) = table.get("comment") {
             cmd.comment = val.to_owned();
         }
+
+        // Several files cannot share one name, unless it is templated.
+        if targets.len() > 1 {
+            for name in [&alias, &dest].into_iter().flatten() {
+                if !is_name_template(name) {
+                    return Err(CharonIoError::AmbiguousAlias(name.to_string_lossy().to_string(), line_num));
+                }
+            }
+        }
+
+        let first_item = self.items.len();
+        for target in targets {
+            let mut item = cmd.clone();
+            let alias = alias.as_ref().map(|alias| render_name_template(alias, &target));
+            let dest = dest.as_ref().map(|dest| render_name_template(dest, &target));
+            item.target = target;
+
+            if item.target.is_dir() {
+                self.add_dir_target(item, alias.or(dest), table, line_num)?;
+            } else {
+                self.add_file_target(item, alias, dest);
+            }
+        }
+
+        // e.g. a.sh and a.py with strip_ext, or {stem} for files in different directories.
+        let items = &self.items[first_item..];
+        for (i, item) in items.iter().enumerate() {
+            if items[..i].iter().any(|other| other.dest == item.dest) {
+                let msg = format!("Two files matching {} would be installed to {:?}. Item declared on line {line_num}", table["target"], item.dest);
+                return Err(CharonIoError::InvalidCharonFile(msg));
+            }
+        }
+        return Ok(());
+    }
+    fn add_file_target(&mut self, mut cmd: InstallItem, alias: Option<PathBuf>, dest: Option<PathBuf>) {
         // alias >> strip_ext >> dest >> target_file_name
         let dest = if let Some(alias) = &alias {
             alias.to_owned()
@@ -111,9 +253,85 @@ impl InstallationCmd {
         cmd.dest.push(dest);
         printinfo!("Copy {target:#?} --> {dest:#?}", target = cmd.target, dest = cmd.dest);
         self.items.push(cmd);
+    }
+    fn add_dir_target(&mut self, template: InstallItem, name: Option<PathBuf>, table: &Map<String, Value>, line_num: usize) -> Result<(), CharonIoError> {
+        //! Add every file below template.target, filtered by the include and exclude lists.
+        //! Files keep their place in the directory tree, under dest/<alias or dir name>.
+        //! If flatten is set, they are placed directly in dest (or dest/<alias>) instead.
+        let include = parse_patterns(table.get("include"), line_num)?;
+        let exclude = parse_patterns(table.get("exclude"), line_num)?;
+        let flatten = matches!(table.get("flatten"), Some(Value::Boolean(true)));
+
+        let root = match (name, flatten) {
+            (Some(name), _) => template.dest.join(name),
+            (None, true) => template.dest.to_owned(),
+            (None, false) => template.dest.join(template.target.file_name().unwrap_or_default()),
+        };
+
+        let files = match walk_dir(&template.target) {
+            Ok(files) => files,
+            Err(err) => return Err(CharonIoError::GenericIoError(err))
+        };
+
+        let first_item = self.items.len();
+        for file in files {
+            let rel = match file.strip_prefix(&template.target) {
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => continue
+            };
+            if !include.is_empty() && !include.iter().any(|pattern| matches_pattern(pattern, &rel)) {
+                continue;
+            }
+            if exclude.iter().any(|pattern| matches_pattern(pattern, &rel)) {
+                continue;
+            }
+
+            let mut dest = if flatten {
+                root.join(rel.file_name().unwrap_or_default())
+            } else {
+                root.join(&rel)
+            };
+            if template.strip_ext {
+                if let Some(stem) = dest.file_stem().map(|stem| stem.to_owned()) {
+                    dest.set_file_name(stem);
+                }
+            }
+
+            if self.items[first_item..].iter().any(|item| item.dest == dest) {
+                let msg = format!("Two files from {:?} would be installed to {dest:?}. Item declared on line {line_num}", template.target);
+                return Err(CharonIoError::InvalidCharonFile(msg));
+            }
+
+            self.add_parent_dirs(&dest, &template.dest);
+            let mut item = template.clone();
+            item.target = file;
+            item.dest = dest;
+            printinfo!("Copy {target:#?} --> {dest:#?}", target = item.target, dest = item.dest);
+            self.items.push(item);
+        }
+
+        if self.items.len() == first_item {
+            printwarn!("No files to install from {:?}. Item declared on line {line_num} of Charon file.", template.target);
+        }
         return Ok(());
     }
-    pub fn add_simple_item(&mut self, target: PathBuf, dest: PathBuf, perms:u32, overwrite: bool, strip_ext: bool) {
+    fn add_parent_dirs(&mut self, file: &Path, base: &Path) {
+        //! Queue every missing directory between base and file for creation, parents first.
+        let mut missing: Vec<PathBuf> = file
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(base) && *dir != base && !dir.exists())
+            .map(|dir| dir.to_path_buf())
+            .collect();
+        missing.reverse();
+
+        for dir in missing {
+            if !self.mkdirs.contains(&dir) {
+                self.mkdirs.push(dir);
+            }
+        }
+    }
+    pub fn add_simple_item(&mut self, target: PathBuf, dest: PathBuf, perms: Option<FileMode>, overwrite: bool, strip_ext: bool) {
         //! Add item without using a toml file.
         let dest = if strip_ext {
             dest.join(PathBuf::from(target.file_stem().unwrap_or_default()).file_name().unwrap_or_default())
@@ -124,8 +342,14 @@ impl InstallationCmd {
             target,
             dest,
             perms,
+            owner: None,
+            group: None,
             strip_ext,
             overwrite,
+            link: false,
+            template: false,
+            contents: None,
+            force: false,
             comment: "".to_string(),
         };
         self.items.push(item);
@@ -158,6 +382,142 @@ impl InstallationCmd {
     }
 }
 
+/// MYTHOS_*_DIR variables, without a trailing '/'.
+pub fn mythos_dir_vars() -> BTreeMap<String, String> {
+    let mythos_dirs = [
+        ("MYTHOS_ALIAS_DIR", MythosDir::Alias),
+        ("MYTHOS_BIN_DIR", MythosDir::Bin),
+        ("MYTHOS_CONFIG_DIR", MythosDir::Config),
+        ("MYTHOS_DATA_DIR", MythosDir::Data),
+        ("MYTHOS_LOCAL_CONFIG_DIR", MythosDir::LocalConfig),
+        ("MYTHOS_LOCAL_DATA_DIR", MythosDir::LocalData),
+    ];
+    let mut vars = BTreeMap::new();
+    for (key, dir) in mythos_dirs {
+        let path = dirs::expand_path(dir, "");
+        vars.insert(key.to_string(), path.to_string_lossy().trim_end_matches('/').to_string());
+    }
+    return vars;
+}
+
+/// Owner and group may be names or numeric ids.
+fn parse_id(val: &Value, line_num: usize) -> Result<String, CharonIoError> {
+    return match val {
+        Value::String(name) if !name.is_empty() => Ok(name.to_string()),
+        Value::Integer(id) if *id >= 0 => Ok(id.to_string()),
+        _ => Err(CharonIoError::InvalidInstallItem(val.to_string(), line_num))
+    };
+}
+
+fn is_glob(target: &str) -> bool {
+    return target.contains(['*', '?', '[']);
+}
+
+/// Returns every path matching the pattern, sorted.
+/// Relative patterns are matched against the directory containing the charon file, never $CWD.
+fn expand_glob(parent: &PathBuf, pattern: &str, line_num: usize) -> Result<Vec<PathBuf>, CharonIoError> {
+    let full_pattern = if Path::new(pattern).is_absolute() {
+        pattern.to_string()
+    } else {
+        format!("{}/{pattern}", Pattern::escape(&parent.to_string_lossy()))
+    };
+
+    let paths = match glob::glob(&full_pattern) {
+        Ok(paths) => paths,
+        Err(_) => return Err(CharonIoError::InvalidInstallItem(pattern.to_string(), line_num))
+    };
+
+    let mut output = Vec::new();
+    for path in paths {
+        match path {
+            Ok(path) => output.push(path),
+            Err(err) => return Err(CharonIoError::GenericIoError(err.into_error()))
+        }
+    }
+    if output.is_empty() {
+        return Err(CharonIoError::GlobMatchedNothing(pattern.to_string(), line_num));
+    }
+    output.sort();
+    return Ok(output);
+}
+
+/// Alias and dest may contain {name}, {stem} or {ext}, which are replaced by the target's.
+fn is_name_template(name: &Path) -> bool {
+    let name = name.to_string_lossy();
+    return name.contains("{name}") || name.contains("{stem}") || name.contains("{ext}");
+}
+
+fn render_name_template(name: &Path, target: &Path) -> PathBuf {
+    let part = |val: Option<&std::ffi::OsStr>| val.unwrap_or_default().to_string_lossy().to_string();
+    return name
+        .to_string_lossy()
+        .replace("{name}", &part(target.file_name()))
+        .replace("{stem}", &part(target.file_stem()))
+        .replace("{ext}", &part(target.extension()))
+        .into();
+}
+
+/// Include and exclude may be a single glob pattern, or a list of them.
+fn parse_patterns(val: Option<&Value>, line_num: usize) -> Result<Vec<Pattern>, CharonIoError> {
+    let patterns: Vec<&Value> = match val {
+        None => return Ok(Vec::new()),
+        Some(Value::Array(list)) => list.iter().collect(),
+        Some(val) => vec![val],
+    };
+
+    let mut output = Vec::new();
+    for pattern in patterns {
+        let pattern = match pattern {
+            Value::String(pattern) => pattern,
+            _ => return Err(CharonIoError::InvalidInstallItem(pattern.to_string(), line_num))
+        };
+        match Pattern::new(pattern) {
+            Ok(pattern) => output.push(pattern),
+            Err(_) => return Err(CharonIoError::InvalidInstallItem(pattern.to_string(), line_num))
+        }
+    }
+    return Ok(output);
+}
+
+/// Patterns containing a '/' are matched against the path relative to the target directory.
+/// Any other pattern is matched against the file name, wherever the file is.
+fn matches_pattern(pattern: &Pattern, rel: &Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    if pattern.as_str().contains('/') {
+        return pattern.matches_path_with(rel, options);
+    }
+    return match rel.file_name() {
+        Some(name) => pattern.matches_with(&name.to_string_lossy(), options),
+        None => false
+    };
+}
+
+/// Every file below dir, sorted so installations are reproducible.
+/// Symlinked directories are skipped, rather than followed.
+fn walk_dir(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut entries: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+
+    let mut output = Vec::new();
+    for path in entries {
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            output.append(&mut walk_dir(&path)?);
+        } else if file_type.is_symlink() && path.is_dir() {
+            printwarn!("Skipping symlinked directory {path:?}.");
+        } else {
+            output.push(path);
+        }
+    }
+    return Ok(output);
+}
+
 /// If user provided relative file path, expand it.
 fn validate(path: &str, charon_path: &PathBuf) -> Result<String, CharonIoError> {
     if path == "." {
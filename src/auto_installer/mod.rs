@@ -2,11 +2,18 @@ mod install_item;
 mod installation_cmd;
 mod charon_io_error;
 mod charon_install_error;
+mod transaction;
+mod file_mode;
+mod template;
+mod hooks;
+mod build;
 
-use std::{ffi::OsString, fs, path::PathBuf};
+use std::{collections::BTreeMap, ffi::OsString, fs, path::PathBuf};
 
 use mythos_core::printinfo;
-use toml::Value;
+use toml::{map::Map, Value};
+
+use crate::dependencies::Dependency;
 
 #[derive(Debug)]
 pub enum CharonIoError { 
@@ -25,15 +32,104 @@ pub enum CharonIoError {
     NoTargetProvided(usize),
     UnknownUtilName(Option<String>),
     InfoSourceBad(PathBuf),
+    // dest: PathBuf
+    InstallFailed(PathBuf, CharonInstallError),
+    // bad_perms: String
+    InvalidPerms(String, usize),
+    // pattern: String
+    GlobMatchedNothing(String, usize),
+    // alias: String
+    AmbiguousAlias(String, usize),
+    // var: String, template: PathBuf
+    UndefinedTemplateVar(String, PathBuf, usize),
+    // util: String
+    NoSourcePath(String),
+    // req: String
+    InvalidVersionReq(String),
+    // util: String
+    NoVersionToPin(String),
+    // cmd: String, stderr: String
+    GitFailed(String, String),
+    // archive: PathBuf, stderr: String
+    ArchiveFailed(PathBuf, String),
+    // target: PathBuf
+    TargetOutsideBundle(PathBuf),
+    // hook: String, status: String
+    HookFailed(String, String),
+    // command: String, status: String
+    BuildFailed(String, String),
+    // dependency: String
+    InvalidDependency(String),
+    // util: String, unmet: Vec<String>
+    UnmetDependencies(String, Vec<String>),
+    // util: String, dependents: Vec<String>
+    RequiredBy(String, Vec<String>),
+    // utils: Vec<String>, starting and ending with the same util
+    DependencyCycle(Vec<String>),
+    // conflicts: Vec<(dest: PathBuf, owner: String)>
+    FileConflict(Vec<(PathBuf, String)>),
+    // name: String
+    InvalidUtilName(String),
+    // util: String, installed: String, source: String
+    SourceVersionMismatch(String, String, String),
 }
 #[derive(Debug)]
 pub enum CharonInstallError {
     GenericIoError(std::io::Error),
     DryRun,
     BadPermissions(std::io::Error),
+    BadOwnership(std::io::Error),
     FileExistsNoOverwrite,
 }
 
+/**
+ * Every change made to the filesystem during an installation.
+ * Used to undo an installation that failed partway through.
+ */
+#[derive(Debug)]
+pub struct Transaction {
+    /// Directories created during installation, parents first.
+    pub created_dirs: Vec<PathBuf>,
+    /// Files which did not exist before installation.
+    pub created_files: Vec<PathBuf>,
+    /// Files which were overwritten, paired with a copy of their original contents.
+    pub backups: Vec<(PathBuf, PathBuf)>,
+}
+
+/**
+ * Options given on the command line, which apply to the whole installation.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct InstallOpts {
+    /// Symlink every item, instead of copying it.
+    pub link: bool,
+    /// Reinstall items even if dest is already up to date.
+    pub force: bool,
+    /// Replaces the source from the info section, e.g. when installing from a git checkout.
+    pub source: Option<String>,
+    /// Skip the build section, and cargo build for rust projects.
+    pub no_build: bool,
+    /// Print the build step instead of running it. Set from do_dry_run by install, repair and pack.
+    pub dry_run: bool,
+    /// Install missing dependencies from the source recorded in the main index.
+    pub with_deps: bool,
+    /// Install files other utils already installed, removing them from the other util's index.
+    pub take_ownership: bool,
+    /// Replaces the util name from the info section or file stem.
+    pub name: Option<String>,
+}
+
+/**
+ * Command run before targets are validated, declared in the build section.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildStep {
+    /// Run using sh -c.
+    pub command: String,
+    pub cwd: PathBuf,
+    pub env: BTreeMap<String, String>,
+}
+
 /**
  * A list of all items that must be installed.
  */
@@ -44,10 +140,62 @@ pub struct InstallationCmd {
     pub name: String,
     /// Location to look for updates.
     pub source: Option<String>,
+    /// Git branch, tag or rev to update from, if source is a git repository.
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub rev: Option<String>,
+    /// Commit the util was installed from, if source is a git repository.
+    pub commit: Option<String>,
     /// Package version
     pub version: Option<String>,
     /// Package description
     pub description: Option<String>,
+    /// Variables declared in the vars section, used to render templates.
+    pub vars: BTreeMap<String, String>,
+    pub hooks: Hooks,
+    /// Utils which must be installed first.
+    pub depends: Vec<Dependency>,
+    /// File name of the charon file, if it is not <name>.charon, e.g. when --name was used.
+    pub charon_file: Option<String>,
+}
+
+/**
+ * Shell commands declared in the hooks section, run before or after a step of the installation.
+ */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Hooks {
+    pub pre_install: Option<String>,
+    pub post_install: Option<String>,
+    pub pre_uninstall: Option<String>,
+    pub post_uninstall: Option<String>,
+    pub post_update: Option<String>,
+    /// Directory containing the charon file. Not saved in the util index, since it may be temporary.
+    /// Defaults to $MYTHOS_DATA_DIR/charon, or / if that does not exist.
+    pub cwd: Option<PathBuf>,
+}
+
+/**
+ * Permissions to apply to an installed file.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileMode {
+    /// Replace permissions entirely, e.g. 0o755.
+    Octal(u32),
+    /// Modify the target's permissions, e.g. "u+x,go-w".
+    Symbolic(Vec<ModeClause>),
+}
+
+/**
+ * One operation of a symbolic mode, e.g. "go-w".
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeClause {
+    /// Bitmask of user (1), group (2) and other (4).
+    pub who: u8,
+    /// One of '+', '-' or '='.
+    pub op: char,
+    /// Any of "rwxXst".
+    pub perms: String,
 }
 
 /**
@@ -59,12 +207,24 @@ pub struct InstallItem {
     pub target: PathBuf,
     /// Path item is to be installed to.
     pub dest: PathBuf,
-    /// Permission of file in 000
-    pub perms: u32,
+    /// Permissions to apply after copying. None keeps the target's permissions.
+    pub perms: Option<FileMode>,
+    /// User name or uid to give ownership of the installed file to.
+    pub owner: Option<String>,
+    /// Group name or gid to give ownership of the installed file to.
+    pub group: Option<String>,
     /// Remove extension from target file name.
     pub strip_ext: bool,
     /// Overwrite file if it already exists?
     pub overwrite: bool,
+    /// Symlink dest to target, instead of copying target.
+    pub link: bool,
+    /// Render target with template::render before installing it.
+    pub template: bool,
+    /// Rendered contents of a template.
+    pub contents: Option<String>,
+    /// Reinstall even if dest is already up to date.
+    pub force: bool,
     /// Comments made during installation process. Used for logging.
     pub comment: String,
 }
@@ -72,7 +232,7 @@ pub struct InstallItem {
 /// Parses installation file.
 /// Assumes path exists.
 /// Returns an error if charon file is invalid.
-pub fn parse_installation_file(path: &PathBuf) -> Result<InstallationCmd, CharonIoError> {
+pub fn parse_installation_file(path: &PathBuf, opts: &InstallOpts) -> Result<InstallationCmd, CharonIoError> {
     let path = find_charon_file(path.to_path_buf())?;
     printinfo!("Reading charon file at {path:?}");
 
@@ -95,6 +255,25 @@ pub fn parse_installation_file(path: &PathBuf) -> Result<InstallationCmd, Charon
         return Err(CharonIoError::CharonFileEmpty);
     }
 
+    let mut cmd = InstallationCmd::new();
+    cmd.name = match (&opts.name, parse_util_name(&path)) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(name)) => name,
+        (None, None) => {
+            return Err(CharonIoError::UnknownUtilName(None)); }
+    };
+    validate_util_name(&cmd.name)?;
+
+    if is_index_style(&file) {
+        if !opts.no_build {
+            BuildStep::run_for(None, &parent, opts.dry_run)?;
+        }
+        printinfo!("\nInstallation Instructions (index-style):");
+        parse_index_style(&mut cmd, &file, &parent)?;
+        set_charon_file(&mut cmd, &path);
+        return Ok(cmd);
+    }
+
     // Read contents of charon file.
     let table = match toml::from_str::<Value>(&file) {
         Ok(Value::Table(table)) => table,
@@ -105,12 +284,11 @@ pub fn parse_installation_file(path: &PathBuf) -> Result<InstallationCmd, Charon
         Err(err) => return Err(CharonIoError::TomlDeError(err)),
     };
 
-    let mut cmd = InstallationCmd::new();
-    cmd.name = match parse_util_name(&path) {
-        Some(name) => name,
-        None => {
-            return Err(CharonIoError::UnknownUtilName(None)); }
-    };
+    // Targets may not exist until the util is built.
+    // Dry runs only print the build step, so targets it would create are reported missing.
+    if !opts.no_build {
+        BuildStep::run_for(table.get("build"), &parent, opts.dry_run)?;
+    }
 
     // Start actually parsing file.
     printinfo!("\nInstallation Instructions:");
@@ -120,8 +298,24 @@ pub fn parse_installation_file(path: &PathBuf) -> Result<InstallationCmd, Charon
             None => {
                 if key.to_lowercase() == "info" {
                     cmd.set_info(&val, &path)?;
+                    if let Some(name) = &opts.name {
+                        cmd.name = name.to_string();
+                    }
+                    validate_util_name(&cmd.name)?;
                     continue;
                 } 
+                if key.to_lowercase() == "vars" {
+                    cmd.set_vars(&val, i)?;
+                    continue;
+                }
+                if key.to_lowercase() == "build" {
+                    continue;
+                }
+                if key.to_lowercase() == "hooks" {
+                    cmd.hooks = Hooks::parse(&val, i)?;
+                    cmd.hooks.cwd = Some(parent.to_owned());
+                    continue;
+                }
                 return Err(CharonIoError::InvalidDirKey(key.to_string(), i));
             }
         };
@@ -135,10 +329,77 @@ pub fn parse_installation_file(path: &PathBuf) -> Result<InstallationCmd, Charon
         }
     }
 
+    // Info and vars may be declared after the items using them.
+    cmd.render_templates()?;
+    set_charon_file(&mut cmd, &path);
+
     return Ok(cmd);
 }
 
-fn find_charon_file(path: PathBuf) -> Result<PathBuf, CharonIoError> {
+fn set_charon_file(cmd: &mut InstallationCmd, path: &PathBuf) {
+    //! Updates look for <name>.charon in the source, unless told otherwise.
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    if file_name != format!("{}.charon", cmd.name) {
+        cmd.charon_file = Some(file_name);
+    }
+}
+
+pub fn is_index_style(file: &str) -> bool {
+    //! Index-style charon files list one `source -> dest` pair per line, instead of toml tables.
+    //! Blank lines and lines starting with '#' are ignored.
+    //! Only the first instruction decides the style, so parse_index_style can report malformed lines after it.
+    let first = file
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("#"));
+
+    return match first.and_then(|line| line.split_once("->")) {
+        // Guard against toml strings which happen to contain an arrow.
+        Some((target, _)) => !target.contains('=') && !target.contains('"'),
+        None => false,
+    };
+}
+
+fn parse_index_style(cmd: &mut InstallationCmd, file: &str, parent: &PathBuf) -> Result<(), CharonIoError> {
+    //! Each line has the form `source -> dest`.
+    //! The first component of dest is a mythos dir shortcut (bin, config, data, ...).
+    //! Anything after it renames the installed file, the same as the alias field.
+    for (i, line) in file.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") { 
+            continue; 
+        }
+
+        let (target, dest) = match line.split_once("->") {
+            Some((target, dest)) => (target.trim(), dest.trim()),
+            None => return Err(CharonIoError::InvalidInstallItem(line.to_string(), line_num)),
+        };
+        if target.is_empty() {
+            return Err(CharonIoError::NoTargetProvided(line_num));
+        }
+
+        let (key, alias) = match dest.split_once("/") {
+            Some((key, alias)) => (key, alias.trim_matches('/')),
+            None => (dest, ""),
+        };
+        let dir = match cmd.add_dir(key) {
+            Some(dir) => dir,
+            None => return Err(CharonIoError::InvalidDirKey(key.to_string(), line_num)),
+        };
+
+        // Build the same table a toml-style charon file would have produced.
+        let mut item = Map::new();
+        item.insert("target".into(), Value::String(target.into()));
+        if !alias.is_empty() {
+            item.insert("alias".into(), Value::String(alias.into()));
+        }
+        cmd.add_item(parent, &dir, &Value::Table(item), line_num)?;
+    }
+    return Ok(());
+}
+
+pub fn find_charon_file(path: PathBuf) -> Result<PathBuf, CharonIoError> {
     //! Path is a file, alleged to be a charon file.
     //! Or path is a directory which must contain a charon file.
     //!     File should must have .charon extension.
@@ -168,6 +429,21 @@ fn find_charon_file(path: PathBuf) -> Result<PathBuf, CharonIoError> {
     return Err(CharonIoError::CharonFileNotFound);
 }
 
+pub fn validate_util_name(name: &str) -> Result<(), CharonIoError> {
+    //! The util name is used to build $MYTHOS_DATA_DIR/charon/<util_name>.charon,
+    //! so it must be a single path component, and must not replace the main index.
+    let invalid = name.is_empty()
+        || name == "."
+        || name == ".."
+        || name == "index"
+        || name.contains(['/', '\\'])
+        || name.contains(char::is_whitespace);
+    if invalid {
+        return Err(CharonIoError::InvalidUtilName(name.to_string()));
+    }
+    return Ok(());
+}
+
 fn parse_util_name(path: &PathBuf) -> Option<String> {
     if path.extension()? == "charon" {
         return Some(path.file_stem()?.to_string_lossy().to_string());
@@ -180,7 +456,6 @@ mod tests {
     use std::env;
 
     use serial_test::serial;
-    use toml::map::Map;
 
     use super::*;
     use crate::main_index;
@@ -198,31 +473,31 @@ mod tests {
     }
     #[test]
     fn file_is_empty() {
-        let res = parse_installation_file(&PathBuf::from("tests/find_charon_file/empty.charon")).unwrap_err();
+        let res = parse_installation_file(&PathBuf::from("tests/find_charon_file/empty.charon"), &InstallOpts::default()).unwrap_err();
         println!("{res}");
         assert!(matches!(res, CharonIoError::CharonFileEmpty));
     }
     #[test] 
     fn charon_file_not_valid_toml() {
-        let res = parse_installation_file(&PathBuf::from("tests/not_toml.charon")).unwrap_err();
+        let res = parse_installation_file(&PathBuf::from("tests/not_toml.charon"), &InstallOpts::default()).unwrap_err();
         println!("{res}");
         assert!(matches!(res, CharonIoError::TomlDeError(_)));
     }
     #[test]
     fn charon_file_invalid_dir_key() {
-        let res = parse_installation_file(&PathBuf::from("tests/invalid_dir_key.charon")).unwrap_err();
+        let res = parse_installation_file(&PathBuf::from("tests/invalid_dir_key.charon"), &InstallOpts::default()).unwrap_err();
         println!("{res}");
         assert!(matches!(res, CharonIoError::InvalidDirKey(_, _)));
     }
     #[test]
     fn charon_file_invalid_item() {
-        let res = parse_installation_file(&PathBuf::from("tests/invalid_install_item.charon")).unwrap_err();
+        let res = parse_installation_file(&PathBuf::from("tests/invalid_install_item.charon"), &InstallOpts::default()).unwrap_err();
         println!("{res}");
         assert!(matches!(res, CharonIoError::InvalidInstallItem(_, _)));
     }
     #[test]
     fn no_target_provided() {
-        let res = parse_installation_file(&PathBuf::from("tests/no_target_provided.charon")).unwrap_err();
+        let res = parse_installation_file(&PathBuf::from("tests/no_target_provided.charon"), &InstallOpts::default()).unwrap_err();
         println!("{res}");
         assert!(matches!(res, CharonIoError::NoTargetProvided(_)));
     }
@@ -240,46 +515,52 @@ mod tests {
             InstallItem { 
                 target: PathBuf::from("tests/valid/targets/config.conf"), 
                 dest: PathBuf::from("tests/valid/dests/etc/valid/config.conf"), 
-                perms: 0,
+                perms: None,
                 strip_ext: false, 
                 overwrite: true, 
-                comment: String::new() 
+                comment: String::new(),
+                ..InstallItem::new()
             },
             InstallItem { 
                 target: PathBuf::from("tests/valid/targets/config.conf"), 
                 dest: PathBuf::from("tests/valid/dests/config/valid/config.conf"), 
-                perms: 0,
+                perms: None,
                 strip_ext: false, 
                 overwrite: false, 
-                comment: String::new() 
+                comment: String::new(),
+                ..InstallItem::new()
             },
             InstallItem { 
                 target: PathBuf::from("tests/valid/targets/1.txt"), 
                 dest: PathBuf::from("tests/valid/dests/data/valid/one.txt"), 
-                perms: 0,
+                perms: None,
                 strip_ext: false, 
                 overwrite: true, 
-                comment: String::new() 
+                comment: String::new(),
+                ..InstallItem::new()
             },
             InstallItem { 
                 target: PathBuf::from("tests/valid/targets/2.txt"), 
                 dest: PathBuf::from("tests/valid/dests/data/valid/2.txt"), 
-                perms: 0,
+                perms: None,
                 strip_ext: false, 
                 overwrite: true, 
-                comment: String::new() 
+                comment: String::new(),
+                ..InstallItem::new()
             },
             InstallItem { 
                 target: PathBuf::from("tests/valid/targets/executable.bin"), 
                 dest: PathBuf::from("tests/valid/dests/bin/executable"), 
-                perms: 0x755,
+                // Written as 0x755 in valid.charon.
+                perms: Some(FileMode::Octal(0o755)),
                 strip_ext: true, 
                 overwrite: true, 
-                comment: String::new() 
+                comment: String::new(),
+                ..InstallItem::new()
             },
         ];
 
-        let res = parse_installation_file(&PathBuf::from("tests/valid/valid.charon")).unwrap();
+        let res = parse_installation_file(&PathBuf::from("tests/valid/valid.charon"), &InstallOpts::default()).unwrap();
         println!("----------------------------------------------------");
         println!("{items:?}");
         for item in res.items {
@@ -289,16 +570,213 @@ mod tests {
         }
     }
     #[serial]
+    #[test]
+    fn ownership_fields() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/valid/dests/data");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/ownership.charon"), &InstallOpts::default()).unwrap();
+        assert_eq!(res.items[0].owner, Some("root".into()));
+        assert_eq!(res.items[0].group, Some("0".into()));
+
+        let res = parse_installation_file(&PathBuf::from("tests/invalid_owner.charon"), &InstallOpts::default()).unwrap_err();
+        assert!(matches!(res, CharonIoError::InvalidInstallItem(_, _)));
+    }
+    #[serial]
+    #[test]
+    fn directory_target() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/dir_target/dests/data");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/dir_target/dir_target.charon"), &InstallOpts::default()).unwrap();
+        let root = PathBuf::from("tests/dir_target/dests/data/dir_target");
+        let dests: Vec<PathBuf> = res.items.iter().map(|item| item.dest.to_owned()).collect();
+
+        assert_eq!(dests, vec![
+            root.join("tree/a.txt"),
+            root.join("tree/b.bak"),
+            root.join("tree/sub/c.txt"),
+            root.join("tree/sub/deep/d.sh"),
+            root.join("flat/a.txt"),
+            root.join("flat/c.txt"),
+            root.join("flat/d.sh"),
+            root.join("only_txt/a.txt"),
+            root.join("only_txt/sub/c.txt"),
+        ]);
+        assert_eq!(res.mkdirs, vec![
+            root.to_owned(),
+            root.join("tree"),
+            root.join("tree/sub"),
+            root.join("tree/sub/deep"),
+            root.join("flat"),
+            root.join("only_txt"),
+            root.join("only_txt/sub"),
+        ]);
+    }
+    #[serial]
+    #[test]
+    fn glob_target() {
+        unsafe {
+            env::set_var("MYTHOS_BIN_DIR", "tests/glob_target/dests/bin");
+            env::set_var("MYTHOS_DATA_DIR", "tests/glob_target/dests/data");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/glob_target/glob_target.charon"), &InstallOpts::default()).unwrap();
+        let dests: Vec<PathBuf> = res.items.iter().map(|item| item.dest.to_owned()).collect();
+
+        assert_eq!(dests, vec![
+            PathBuf::from("tests/glob_target/dests/bin/a"),
+            PathBuf::from("tests/glob_target/dests/bin/b"),
+            PathBuf::from("tests/glob_target/dests/data/glob_target/y_icon.png"),
+            PathBuf::from("tests/glob_target/dests/data/glob_target/x_icon.png"),
+        ]);
+        assert_eq!(res.items[0].perms, Some(FileMode::Octal(0o755)));
+        assert_eq!(res.items[1].perms, Some(FileMode::Octal(0o755)));
+    }
+    #[test]
+    fn glob_matched_nothing() {
+        let res = parse_installation_file(&PathBuf::from("tests/glob_target/no_match.charon"), &InstallOpts::default()).unwrap_err();
+        println!("{res}");
+        assert!(matches!(res, CharonIoError::GlobMatchedNothing(_, _)));
+
+        let res = parse_installation_file(&PathBuf::from("tests/glob_target/cwd_match.charon"), &InstallOpts::default()).unwrap_err();
+        assert!(matches!(res, CharonIoError::GlobMatchedNothing(_, _)));
+    }
+    #[test]
+    fn glob_duplicate_dest() {
+        let res = parse_installation_file(&PathBuf::from("tests/glob_target/duplicate_dest.charon"), &InstallOpts::default()).unwrap_err();
+        println!("{res}");
+        assert!(matches!(res, CharonIoError::InvalidCharonFile(msg) if msg.starts_with("Two files")));
+    }
+    #[test]
+    fn glob_ambiguous_alias() {
+        let res = parse_installation_file(&PathBuf::from("tests/glob_target/ambiguous_alias.charon"), &InstallOpts::default()).unwrap_err();
+        println!("{res}");
+        assert!(matches!(res, CharonIoError::AmbiguousAlias(_, _)));
+    }
+    #[serial]
+    #[test]
+    fn link_field() {
+        unsafe {
+            env::set_var("MYTHOS_BIN_DIR", "tests/valid/dests/bin");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/link.charon"), &InstallOpts::default()).unwrap();
+        assert!(res.items[0].link);
+        assert!(!res.items[1].link);
+    }
+    #[serial]
+    #[test]
+    fn template_field() {
+        unsafe {
+            env::set_var("MYTHOS_CONFIG_DIR", "tests/template/dests/etc");
+            env::set_var("CHARON_TEST_HOME", "/home/charon");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/template/template.charon"), &InstallOpts::default()).unwrap();
+        assert!(res.items[0].template);
+        assert_eq!(res.items[0].contents, Some("# template v1.2.3\ngreeting = hello\nhome = /home/charon\nliteral = ${not_a_var}\n".into()));
+
+        let res = parse_installation_file(&PathBuf::from("tests/template/undefined.charon"), &InstallOpts::default()).unwrap_err();
+        println!("{res}");
+        assert!(matches!(res, CharonIoError::UndefinedTemplateVar(_, _, 2)));
+    }
+    #[serial]
+    #[test]
+    fn build_section() {
+        let _ = fs::remove_dir_all("tests/build/out");
+        let res = parse_installation_file(&PathBuf::from("tests/build/build.charon"), &InstallOpts::default()).unwrap();
+        assert!(res.items[0].target.ends_with("tests/build/out/built.txt"));
+        assert_eq!(fs::read_to_string("tests/build/out/built.txt").unwrap(), "hello");
+
+        let res = parse_installation_file(&PathBuf::from("tests/build/failed_build.charon"), &InstallOpts::default()).unwrap_err();
+        assert!(matches!(res, CharonIoError::BuildFailed(_, _)));
+
+        let opts = InstallOpts { no_build: true, ..InstallOpts::default() };
+        let res = parse_installation_file(&PathBuf::from("tests/build/failed_build.charon"), &opts).unwrap_err();
+        assert!(matches!(res, CharonIoError::TargetFileNotFound(_, _)));
+    }
+
+    #[test]
+    fn dry_run_build() {
+        let _ = fs::remove_dir_all("tests/build/dry_run/out");
+        let opts = InstallOpts { dry_run: true, ..InstallOpts::default() };
+        let res = parse_installation_file(&PathBuf::from("tests/build/dry_run/build.charon"), &opts).unwrap_err();
+        assert!(matches!(res, CharonIoError::TargetFileNotFound(_, _)));
+        assert!(!PathBuf::from("tests/build/dry_run/out").exists());
+    }
+
     #[test]
     fn empty_dir_field() {
         unsafe {
             env::set_var("MYTHOS_DATA_DIR", "tests/valid/dests/data1");
         }
-        let res = parse_installation_file(&PathBuf::from("tests/valid/empty_dir_field.charon")).unwrap();
+        let res = parse_installation_file(&PathBuf::from("tests/valid/empty_dir_field.charon"), &InstallOpts::default()).unwrap();
         assert_eq!(res.mkdirs, vec![PathBuf::from("tests/valid/dests/data1/empty_dir_field")])
     }
     #[serial]
     #[test]
+    fn index_style_charon_file() {
+        unsafe {
+            env::set_var("MYTHOS_CONFIG_DIR", "tests/index_style/dests/etc");
+            env::set_var("MYTHOS_BIN_DIR", "tests/index_style/dests/bin");
+            env::set_var("MYTHOS_DATA_DIR", "tests/index_style/dests/data");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/index_style/index_style.charon"), &InstallOpts::default()).unwrap();
+        let dests: Vec<PathBuf> = res.items.iter().map(|item| item.dest.to_owned()).collect();
+
+        assert_eq!(dests, vec![
+            PathBuf::from("tests/index_style/dests/etc/index_style/config.conf"),
+            PathBuf::from("tests/index_style/dests/bin/executable"),
+            PathBuf::from("tests/index_style/dests/data/index_style/one.txt"),
+        ]);
+        assert_eq!(res.items[0].target, PathBuf::from("tests/valid/targets/config.conf").canonicalize().unwrap());
+    }
+    #[test]
+    fn index_style_invalid_dir_key() {
+        let res = parse_installation_file(&PathBuf::from("tests/index_style/invalid_dir_key.charon"), &InstallOpts::default()).unwrap_err();
+        println!("{res}");
+        assert!(matches!(res, CharonIoError::InvalidDirKey(_, 2)));
+    }
+    #[serial]
+    #[test]
+    fn index_style_malformed_line() {
+        unsafe {
+            env::set_var("MYTHOS_CONFIG_DIR", "tests/index_style/dests/etc");
+            env::set_var("MYTHOS_BIN_DIR", "tests/index_style/dests/bin");
+        }
+        let res = parse_installation_file(&PathBuf::from("tests/index_style/malformed.charon"), &InstallOpts::default()).unwrap_err();
+        assert!(matches!(res, CharonIoError::InvalidInstallItem(line, 3) if line == "../valid/targets/executable.bin bin/executable"));
+    }
+
+    #[test]
+    fn toml_with_arrow_is_not_index_style() {
+        assert!(!is_index_style("data = [ { target = \"a->b\" } ]"));
+        assert!(is_index_style("# comment\na.txt -> data\n"));
+        assert!(!is_index_style("# comment only\n"));
+    }
+    #[serial]
+    #[test]
+    fn util_name_override() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/index_style/dests/data");
+        }
+        let opts = InstallOpts { name: Some("renamed".into()), ..InstallOpts::default() };
+        let res = parse_installation_file(&PathBuf::from("tests/index_style/index_style.charon"), &opts).unwrap();
+        assert_eq!(res.name, "renamed");
+        assert_eq!(res.charon_file, Some("index_style.charon".to_string()));
+        assert!(res.items.iter().any(|item| item.dest == PathBuf::from("tests/index_style/dests/data/renamed/one.txt")));
+
+        let opts = InstallOpts { name: Some("../x".into()), ..InstallOpts::default() };
+        let res = parse_installation_file(&PathBuf::from("tests/index_style/index_style.charon"), &opts).unwrap_err();
+        assert!(matches!(res, CharonIoError::InvalidUtilName(_)));
+    }
+    #[test]
+    fn invalid_util_names() {
+        for name in ["", ".", "..", "index", "a/b", "a\\b", "my util"] {
+            assert!(matches!(validate_util_name(name), Err(CharonIoError::InvalidUtilName(_))), "{name}");
+        }
+        assert!(validate_util_name("charon-2").is_ok());
+    }
+    #[serial]
+    #[test]
     fn write_main_index() {
         unsafe {
             env::set_var("MYTHOS_DATA_DIR", "tests/valid/dests/data/main_index/");
@@ -0,0 +1,69 @@
+use std::{collections::BTreeMap, env, path::Path};
+
+use super::CharonIoError;
+
+/// Replace every ${var} in contents.
+/// Variables are looked up in vars first, then in the environment.
+/// $${var} is written out as a literal ${var}.
+pub fn render(contents: &str, vars: &BTreeMap<String, String>, path: &Path) -> Result<String, CharonIoError> {
+    let mut output = String::with_capacity(contents.len());
+
+    for (i, line) in contents.split_inclusive('\n').enumerate() {
+        let mut rest = line;
+        while let Some(start) = rest.find('$') {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+
+            if let Some(escaped) = after.strip_prefix("${") {
+                output.push_str("${");
+                rest = escaped;
+                continue;
+            }
+
+            let end = match after.find('}') {
+                Some(end) if after.starts_with('{') => end,
+                _ => {
+                    // Not a variable, leave it alone.
+                    output.push('$');
+                    rest = after;
+                    continue;
+                }
+            };
+
+            let name = &after[1..end];
+            let value = match vars.get(name) {
+                Some(value) => value.to_owned(),
+                None => match env::var(name) {
+                    Ok(value) => value,
+                    Err(_) => return Err(CharonIoError::UndefinedTemplateVar(name.to_string(), path.to_path_buf(), i + 1)),
+                }
+            };
+            output.push_str(&value);
+            rest = &after[end + 1..];
+        }
+        output.push_str(rest);
+    }
+    return Ok(output);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn render_vars() {
+        let mut vars = BTreeMap::new();
+        vars.insert("name".to_string(), "charon".to_string());
+
+        let res = render("a = ${name}\nb = $${name}\nc = $5 ${ name\n", &vars, &PathBuf::new()).unwrap();
+        assert_eq!(res, "a = charon\nb = ${name}\nc = $5 ${ name\n");
+    }
+
+    #[test]
+    fn render_undefined_var() {
+        let res = render("a\nb = ${charon_undefined_var}\n", &BTreeMap::new(), &PathBuf::new()).unwrap_err();
+        assert!(matches!(res, CharonIoError::UndefinedTemplateVar(_, _, 2)));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{fs, io, os::unix::fs::symlink, path::{Path, PathBuf}};
+
+use mythos_core::{printerror, printinfo, printwarn};
+
+use super::Transaction;
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        return Transaction {
+            created_dirs: Vec::new(),
+            created_files: Vec::new(),
+            backups: Vec::new(),
+        };
+    }
+
+    pub fn create_dir_all(&mut self, dir: &Path) -> io::Result<()> {
+        //! Like fs::create_dir_all, but remembers every directory it had to create.
+        let mut missing: Vec<PathBuf> = Vec::new();
+        let mut current = Some(dir);
+        while let Some(path) = current {
+            if path.as_os_str().is_empty() || path.exists() {
+                break;
+            }
+            missing.push(path.to_path_buf());
+            current = path.parent();
+        }
+
+        fs::create_dir_all(dir)?;
+        self.created_dirs.extend(missing.into_iter().rev());
+        return Ok(());
+    }
+
+    pub fn protect(&mut self, path: &Path) -> io::Result<()> {
+        //! Must be called before path is modified.
+        //! Existing files are backed up, new files are deleted on rollback.
+        if self.is_tracked(path) {
+            return Ok(());
+        }
+
+        if fs::symlink_metadata(path).is_err() {
+            self.created_files.push(path.to_path_buf());
+            return Ok(());
+        }
+
+        let backup = sibling(path, "charon-bak");
+        copy_file_or_link(path, &backup)?;
+        self.backups.push((path.to_path_buf(), backup));
+        return Ok(());
+    }
+
+    pub fn stage_copy(&mut self, target: &Path, dest: &Path) -> io::Result<PathBuf> {
+        //! Copy target next to dest, so install_file can move it into place with a single rename.
+        //! Target's mtime is kept, so later installations can tell dest is unchanged without reading it.
+        let staged = sibling(dest, "charon-tmp");
+        let res = fs::copy(target, &staged)
+            .and_then(|_| fs::File::options().write(true).open(&staged)?.set_modified(fs::metadata(target)?.modified()?));
+        if let Err(err) = res {
+            let _ = fs::remove_file(&staged);
+            return Err(err);
+        }
+        return Ok(staged);
+    }
+
+    pub fn stage_contents(&mut self, target: &Path, contents: &str, dest: &Path) -> io::Result<PathBuf> {
+        //! Like stage_copy, but writes contents instead of target's contents.
+        //! Target's permissions are kept.
+        let staged = sibling(dest, "charon-tmp");
+        let res = fs::write(&staged, contents)
+            .and_then(|_| fs::set_permissions(&staged, fs::metadata(target)?.permissions()));
+        if let Err(err) = res {
+            let _ = fs::remove_file(&staged);
+            return Err(err);
+        }
+        return Ok(staged);
+    }
+
+    pub fn stage_link(&mut self, target: &Path, dest: &Path) -> io::Result<PathBuf> {
+        //! Like stage_copy, but creates a symlink to the canonical target path.
+        let staged = sibling(dest, "charon-tmp");
+        let _ = fs::remove_file(&staged);
+        symlink(fs::canonicalize(target)?, &staged)?;
+        return Ok(staged);
+    }
+
+    pub fn install_file(&mut self, staged: &Path, dest: &Path) -> io::Result<()> {
+        //! Atomically replace dest with a file created by stage_copy.
+        let res = self.protect(dest).and_then(|_| fs::rename(staged, dest));
+        if res.is_err() {
+            let _ = fs::remove_file(staged);
+        }
+        return res;
+    }
+
+    pub fn write(&mut self, path: &Path, contents: &str) -> io::Result<()> {
+        let staged = sibling(path, "charon-tmp");
+        fs::write(&staged, contents)?;
+        return self.install_file(&staged, path);
+    }
+
+    pub fn rollback(self) {
+        //! Undo every change made during installation.
+        //! Errors are reported, but do not stop the rest of the rollback.
+        printinfo!("\nRolling back installation...");
+        for path in self.created_files.iter().rev() {
+            match fs::remove_file(path) {
+                Ok(_) => printinfo!("Removed file: {path:?}"),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => printerror!("Could not remove {path:?}. Error = {err}."),
+            }
+        }
+        for (path, backup) in self.backups.iter().rev() {
+            match fs::rename(backup, path) {
+                Ok(_) => printinfo!("Restored file: {path:?}"),
+                Err(err) => printerror!("Could not restore {path:?} from {backup:?}. Error = {err}."),
+            }
+        }
+        for dir in self.created_dirs.iter().rev() {
+            match fs::remove_dir(dir) {
+                Ok(_) => printinfo!("Removed dir: {dir:?}"),
+                Err(err) => printerror!("Could not remove {dir:?}. Error = {err}."),
+            }
+        }
+    }
+
+    pub fn commit(self) {
+        //! Installation succeeded, backups are no longer needed.
+        for (_, backup) in &self.backups {
+            if let Err(err) = fs::remove_file(backup) {
+                printwarn!("Could not remove backup {backup:?}. Error = {err}.");
+            }
+        }
+    }
+
+    fn is_tracked(&self, path: &Path) -> bool {
+        return self.created_files.iter().any(|file| file == path)
+            || self.backups.iter().any(|(file, _)| file == path);
+    }
+}
+
+fn sibling(path: &Path, ext: &str) -> PathBuf {
+    //! Hidden file next to path, e.g. dir/.name.ext
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    return path.with_file_name(format!(".{name}.{ext}"));
+}
+
+fn copy_file_or_link(from: &Path, to: &Path) -> io::Result<()> {
+    //! Symlinks are copied as links, rather than copying whatever they point to.
+    let _ = fs::remove_file(to);
+    if fs::symlink_metadata(from)?.file_type().is_symlink() {
+        return symlink(fs::read_link(from)?, to);
+    }
+    fs::copy(from, to)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn rollback_restores_files() {
+        let root = env::temp_dir().join("charon_transaction_rollback");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("existing"), "old").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.create_dir_all(&root.join("a/b")).unwrap();
+        transaction.write(&root.join("a/b/new"), "new").unwrap();
+        transaction.write(&root.join("existing"), "new").unwrap();
+        assert_eq!(fs::read_to_string(root.join("existing")).unwrap(), "new");
+        assert_eq!(transaction.created_dirs, vec![root.join("a"), root.join("a/b")]);
+
+        transaction.rollback();
+        assert_eq!(fs::read_to_string(root.join("existing")).unwrap(), "old");
+        assert!(!root.join("a").exists());
+        assert!(!root.join(".existing.charon-bak").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rollback_removes_links() {
+        let root = env::temp_dir().join("charon_transaction_link");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("target"), "target").unwrap();
+
+        let mut transaction = Transaction::new();
+        let staged = transaction.stage_link(&root.join("target"), &root.join("link")).unwrap();
+        transaction.install_file(&staged, &root.join("link")).unwrap();
+        assert!(fs::symlink_metadata(root.join("link")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), root.join("target").canonicalize().unwrap());
+
+        transaction.rollback();
+        assert!(fs::symlink_metadata(root.join("link")).is_err());
+        assert!(root.join("target").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn commit_removes_backups() {
+        let root = env::temp_dir().join("charon_transaction_commit");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("existing"), "old").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.write(&root.join("existing"), "new").unwrap();
+        assert!(root.join(".existing.charon-bak").exists());
+
+        transaction.commit();
+        assert_eq!(fs::read_to_string(root.join("existing")).unwrap(), "new");
+        assert!(!root.join(".existing.charon-bak").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+/*!
+ * Dependencies between utils, declared in the info section: depends = ["other_util>=1.2", "helper"].
+ * Requirements are checked against the versions recorded in the main index.
+ */
+
+use std::fmt;
+use toml::{map::Map, Value};
+use crate::version::{Version, VersionReq};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    /// None if any version will do.
+    pub req: Option<VersionReq>,
+    /// As written in the charon file.
+    pub raw: String,
+}
+
+impl Dependency {
+    pub fn parse(val: &str) -> Option<Dependency> {
+        //! The util name ends at the first operator or space: "util", "util>=1.2", "util ^1.2".
+        let val = val.trim();
+        let end = val.find(|c: char| "<>=^~* ".contains(c)).unwrap_or(val.len());
+        let (name, req) = val.split_at(end);
+        if name.is_empty() {
+            return None;
+        }
+
+        let req = match req.trim() {
+            "" => None,
+            req => Some(VersionReq::parse(req)?),
+        };
+        return Some(Dependency { name: name.to_string(), req, raw: val.to_string() });
+    }
+
+    pub fn is_met(&self, main_index: &Map<String, Value>) -> bool {
+        let entry = match main_index.get(&self.name) {
+            Some(entry) => entry,
+            None => return false
+        };
+        let req = match &self.req {
+            Some(req) => req,
+            None => return true
+        };
+        return match entry.get("version") {
+            Some(Value::String(version)) => Version::parse(version).is_some_and(|version| req.matches(&version)),
+            _ => false
+        };
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.raw);
+    }
+}
+
+pub fn from_entry(entry: &Value) -> Vec<Dependency> {
+    //! Dependencies recorded in a main index entry. Invalid ones are ignored, they were checked on install.
+    return match entry.get("depends") {
+        Some(Value::Array(depends)) => depends
+            .iter()
+            .filter_map(|dep| dep.as_str())
+            .filter_map(Dependency::parse)
+            .collect(),
+        _ => Vec::new()
+    };
+}
+
+pub fn unmet(depends: &[Dependency], main_index: &Map<String, Value>) -> Vec<Dependency> {
+    return depends.iter().filter(|dep| !dep.is_met(main_index)).cloned().collect();
+}
+
+pub fn dependents(util: &str, main_index: &Map<String, Value>) -> Vec<String> {
+    //! Installed utils which depend on util.
+    return main_index
+        .iter()
+        .filter(|(_, entry)| from_entry(entry).iter().any(|dep| dep.name == util))
+        .map(|(name, _)| name.to_string())
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn main_index() -> Map<String, Value> {
+        let index = "[host]\nversion = \"1.4.0\"\n\n[plugin]\nversion = \"0.1.0\"\ndepends = [\"host>=1.2\"]\n";
+        return toml::from_str(index).unwrap();
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        let dep = Dependency::parse("host>=1.2").unwrap();
+        assert_eq!(dep.name, "host");
+        assert!(dep.req.is_some());
+        assert_eq!(Dependency::parse("host").unwrap().req, None);
+        assert_eq!(Dependency::parse("host ^1.2").unwrap().name, "host");
+
+        assert!(Dependency::parse(">=1.2").is_none());
+        assert!(Dependency::parse("host>=latest").is_none());
+    }
+
+    #[test]
+    fn check_dependencies() {
+        let index = main_index();
+        let depends: Vec<Dependency> = ["host>=1.2", "host>=2", "helper"]
+            .iter()
+            .filter_map(|dep| Dependency::parse(dep))
+            .collect();
+
+        let unmet: Vec<String> = unmet(&depends, &index).iter().map(|dep| dep.to_string()).collect();
+        assert_eq!(unmet, vec!["host>=2", "helper"]);
+        assert_eq!(dependents("host", &index), vec!["plugin"]);
+        assert!(dependents("plugin", &index).is_empty());
+    }
+}
@@ -3,27 +3,55 @@
  * Reads a toml-style file containing installation instructions.
  */
 
-use std::{env, fs, path::PathBuf};
+use std::{env, fs, path::{Path, PathBuf}};
 
+mod archive;
 mod auto_installer;
+mod dependencies;
 mod uninstaller;
 mod main_index;
+mod packer;
+mod query;
+mod repairer;
 mod updater;
+mod util_index;
+mod verifier;
+mod version;
 
-use auto_installer::{parse_installation_file, CharonIoError, InstallationCmd};
-use mythos_core::{cli::clean_cli_args, dirs, printerror, printinfo};
+use auto_installer::{parse_installation_file, validate_util_name, CharonIoError, InstallOpts, InstallationCmd, Transaction};
+use mythos_core::{cli::clean_cli_args, dirs, printerror, printinfo, printwarn};
+use auto_installer::InstallItem;
+use dependencies::Dependency;
+use toml::Value;
+use util_index::{DirRecord, FileRecord, UtilIndex};
 
 fn main() {
     let mut do_dry_run = false;
+    let mut opts = InstallOpts::default();
+    let mut do_pack = false;
     let mut path = None;
     let mut args = clean_cli_args();
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "-h" | "--help" => {
-                println!("charon [opts] [path]|[utils...]\nBasic installer util that can use toml files to quickly install programs.\nopts:\n-h | --help\t\tPrint this menu.\n-n | --dryrun\t\tRun command without making changes to filesystem.\n-r | --remove\t\tDeletes all files installed by mythos utils. The util must have been installed using charon.\n-u | --update\t\tUsing the source paths provided in index.charon, check if any utils can be updated.\n-U | --force-update\tForce update. Takes a list of utils which have been installed using charon.\n-l | --list\t\tShow list of utils installed using charon.\n-L\t\t\tLike -l, but more verbose.\n--src\t\t\tLike -l, but show sources as well.");
+                println!("charon [opts] [path]|[utils...]\nBasic installer util that can use toml files to quickly install programs.\npath may be a directory, a charon file, or a .tar.gz, .tar.zst or .zip archive with a charon file at its root.\nopts:\n-h | --help\t\tPrint this menu.\n-n | --dryrun\t\tRun command without making changes to filesystem.\n--link\t\t\tSymlink installed files to their targets, instead of copying them.\n--force\t\t\tReinstall files even if they are unchanged.\n--no-build\t\tSkip the build step.\n--with-deps\t\tInstall missing dependencies from the source recorded in the main index.\n--take-ownership\tInstall files other utils installed, and remove them from those utils.\n--name\t\t\tInstall the util under this name, instead of the one from the charon file.\n-r | --remove\t\tDeletes all files installed by mythos utils. The util must have been installed using charon.\n--cascade\t\tWith -r, also remove utils depending on the removed ones.\n-u | --update\t\tUsing the source paths provided in index.charon, check if any utils can be updated.\n-U | --force-update\tForce update. Takes a list of utils which have been installed using charon.\n-l | --list\t\tShow list of utils installed using charon.\n-L\t\t\tLike -l, but more verbose.\n--src\t\t\tLike -l, but show sources as well.\n--owns\t\t\tShow which util installed a file.\n--files\t\t\tList every file a util installed.\n--verify\t\tCheck installed files against the util index. Takes a list of utils, defaults to all of them. Exits with 1 if anything changed.\n--repair\t\tReinstall missing or modified files of a util from its source.\n--pin\t\t\tHold back updates for a util. Takes util or util=version-req, defaults to the installed version.\n--unpin\t\t\tAllow updates for a util again.\n--pack\t\t\tBundle the charon file at path (defaults to $CWD) and its targets into <name>-<version>.tar.gz.");
                 return;
             },
             "-n" | "--dryrun" => do_dry_run = true,
+            "--link" => opts.link = true,
+            "--force" => opts.force = true,
+            "--no-build" => opts.no_build = true,
+            "--with-deps" => opts.with_deps = true,
+            "--take-ownership" => opts.take_ownership = true,
+            "--name" => {
+                opts.name = match args.next() {
+                    Some(name) => Some(name),
+                    None => {
+                        printerror!("--name requires a util name.");
+                        return;
+                    }
+                };
+            },
             "-r" | "--remove" => {
                 uninstall(args, do_dry_run);
                 return;
@@ -41,13 +69,92 @@ fn main() {
                 return;
             },
             "-u" | "--update" => {
-                if let Err(err) = updater::update(do_dry_run) {
+                if let Err(err) = updater::update(&opts, do_dry_run) {
+                    printerror!("{err}");
+                }
+                return;
+            },
+            "--owns" => {
+                let path = match args.next() {
+                    Some(path) => PathBuf::from(path),
+                    None => {
+                        printerror!("--owns requires a path.");
+                        return;
+                    }
+                };
+                match query::find_owner(&path) {
+                    Ok(true) => return,
+                    Ok(false) => std::process::exit(1),
+                    Err(err) => {
+                        printerror!("{err}");
+                        std::process::exit(2);
+                    }
+                }
+            },
+            "--files" => {
+                let util = match args.next() {
+                    Some(util) => util,
+                    None => {
+                        printerror!("--files requires a util name.");
+                        return;
+                    }
+                };
+                if let Err(err) = validate_util_name(&util).and_then(|_| query::list_files(&util)) {
+                    printerror!("{err}");
+                }
+                return;
+            },
+            "--verify" => {
+                let utils: Vec<String> = args.collect();
+                match validate_util_names(&utils).and_then(|_| verifier::verify_utils(utils)) {
+                    Ok(true) => return,
+                    Ok(false) => std::process::exit(1),
+                    Err(err) => {
+                        printerror!("{err}");
+                        std::process::exit(2);
+                    }
+                }
+            },
+            "--pin" | "--unpin" => {
+                let util = match args.next() {
+                    Some(util) => util,
+                    None => {
+                        printerror!("{arg} requires a util name.");
+                        return;
+                    }
+                };
+                // --pin takes util=version-req.
+                let name = util.split_once('=').map_or(util.as_str(), |(name, _)| name.trim());
+                let res = if let Err(err) = validate_util_name(name) {
+                    Err(err)
+                } else if arg == "--pin" {
+                    updater::pin(&util, do_dry_run)
+                } else {
+                    updater::unpin(&util, do_dry_run)
+                };
+                if let Err(err) = res {
+                    printerror!("{err}");
+                }
+                return;
+            },
+            "--pack" => do_pack = true,
+            "--repair" => {
+                let util = match args.next() {
+                    Some(util) => util,
+                    None => {
+                        printerror!("--repair requires a util name.");
+                        return;
+                    }
+                };
+                if let Err(err) = validate_util_name(&util).and_then(|_| repairer::repair_util(&util, &opts, do_dry_run)) {
                     printerror!("{err}");
                 }
                 return;
             },
             "-U" | "--force-update" => {
-                if let Err(err) = updater::force_update(args, do_dry_run) {
+                let utils: Vec<String> = args.collect();
+                let res = validate_util_names(&utils).and_then(|_| updater::force_update(utils.into_iter(), &opts, do_dry_run));
+                if let Err(err) = res {
                     printerror!("{err}");
                 }
                 return;
@@ -73,28 +180,178 @@ fn main() {
         }
     };
 
-    match install(&path, do_dry_run) {
-        Ok(()) => printinfo!("\nInstallation complete!"),
+    if do_pack {
+        let out_dir = match env::current_dir() {
+            Ok(dir) => dir,
+            Err(err) => {
+                printerror!("Could not get $CWD. Error = {err:?}.");
+                return;
+            }
+        };
+        if let Err(err) = packer::pack(&path, &out_dir, &opts, do_dry_run) {
+            printerror!("{err}");
+        }
+        return;
+    }
+
+    match install(&path, &opts, do_dry_run) {
+        Ok(index) => {
+            print_modified_files(&index.modified_files());
+            printinfo!("\nInstallation complete!");
+        },
         Err(err) => printerror!("{err}")
     }
 }
 
-pub fn install(path: &PathBuf, do_dry_run: bool) -> Result<(), CharonIoError> {
+pub fn install(path: &PathBuf, opts: &InstallOpts, do_dry_run: bool) -> Result<UtilIndex, CharonIoError> {
+    //! Returns the new util index.
+    //! Path may also be an archive, which is unpacked to a temporary directory first.
+    return install_with_deps(path, opts, do_dry_run, &[]);
+}
+
+fn install_with_deps(path: &PathBuf, opts: &InstallOpts, do_dry_run: bool, visiting: &[String]) -> Result<UtilIndex, CharonIoError> {
+    //! Visiting holds the utils whose dependencies are being installed, which led to this one.
+    let opts = &InstallOpts { dry_run: do_dry_run, ..opts.clone() };
+    let unpacked = if archive::is_archive(path) {
+        Some(archive::unpack_temp(path)?)
+    } else {
+        None
+    };
+
     // Find valid .charon file.
     // Parse .charon file => InstallationCmd.
-    let mut cmd = parse_installation_file(&path)?;
+    let mut cmd = match &unpacked {
+        Some(dir) => parse_installation_file(&dir.path, opts)?,
+        None => parse_installation_file(&path, opts)?
+    };
     let util_name = cmd.name.clone();
 
-    // Install files.
-    printinfo!("\nBeginning installation.");
-    let new_charon_index = copy_files(&mut cmd, do_dry_run);
+    if unpacked.is_some() {
+        // Updates compare against whatever archive is found at this path.
+        cmd.source = Some(path.canonicalize()?.to_string_lossy().to_string());
+        if opts.link {
+            printwarn!("Files from archives are unpacked to a temporary directory, so they cannot be linked. Copying them instead.");
+        }
+    } else if opts.link {
+        // Templates are rendered, so they can never be links.
+        for item in cmd.items.iter_mut().filter(|item| !item.template) {
+            item.link = true;
+        }
+    }
+    for item in &mut cmd.items {
+        item.force = opts.force;
+    }
+    if let Some(source) = &opts.source {
+        cmd.source = Some(source.to_string());
+    }
+    if let Some(source) = &cmd.source {
+        let dir = if path.is_dir() { path.as_path() } else { path.parent().unwrap_or(Path::new(".")) };
+        cmd.commit = updater::source_commit(source, dir);
+    }
 
     // Load old charon file, if it exists.
     let old_charon_index = read_util_index(&util_name, do_dry_run)?;
+    let is_update = !old_charon_index.files.is_empty();
+
+    check_dependencies(&cmd, opts, do_dry_run, visiting)?;
+
+    // Files other utils installed. Uninstalling those utils would delete them.
+    let conflicts = find_conflicts(&cmd, do_dry_run)?;
+    if !conflicts.is_empty() && !opts.take_ownership {
+        let conflicts = conflicts.into_iter().map(|(owner, dest)| (dest, owner)).collect();
+        return Err(CharonIoError::FileConflict(conflicts));
+    }
+
+    // Nothing has been changed yet, so a failing pre_install hook needs no rollback.
+    cmd.hooks.run("pre_install", &util_name, cmd.version.as_ref(), do_dry_run)?;
+
+    // Install files and write indexes.
+    // If anything fails, undo every change made so far.
+    printinfo!("\nBeginning installation.");
+    let mut transaction = Transaction::new();
+    let new_charon_index = match stage_installation(&mut cmd, &old_charon_index, &conflicts, &mut transaction, do_dry_run) {
+        Ok(index) => index,
+        Err(err) => {
+            transaction.rollback();
+            return Err(err);
+        }
+    };
+    transaction.commit();
 
     // Remove orphans.
     process_orphans(old_charon_index, &new_charon_index, do_dry_run);
 
+    // Installation already succeeded, so failing post hooks only get a warning.
+    let mut post_hooks = vec!["post_install"];
+    if is_update {
+        post_hooks.push("post_update");
+    }
+    for hook in post_hooks {
+        if let Err(err) = cmd.hooks.run(hook, &util_name, cmd.version.as_ref(), do_dry_run) {
+            printwarn!("{err}");
+        }
+    }
+
+    return Ok(new_charon_index);
+}
+
+fn check_dependencies(cmd: &InstallationCmd, opts: &InstallOpts, do_dry_run: bool, visiting: &[String]) -> Result<(), CharonIoError> {
+    //! With --with-deps, unmet dependencies are (re)installed from the source recorded in the main index.
+    //! Fails if a dependency is already being installed further up, instead of installing it forever.
+    let index = main_index::load_main_index(do_dry_run)?;
+    let unmet = dependencies::unmet(&cmd.depends, &index);
+    if unmet.is_empty() {
+        return Ok(());
+    }
+    let unmet_error = |deps: &Vec<Dependency>| CharonIoError::UnmetDependencies(
+        cmd.name.clone(), deps.iter().map(|dep| dep.to_string()).collect());
+    if !opts.with_deps {
+        return Err(unmet_error(&unmet));
+    }
+
+    let mut visiting = visiting.to_vec();
+    visiting.push(cmd.name.to_string());
+    for dep in &unmet {
+        if let Some(i) = visiting.iter().position(|util| util == &dep.name) {
+            let mut cycle = visiting[i..].to_vec();
+            cycle.push(dep.name.to_string());
+            return Err(CharonIoError::DependencyCycle(cycle));
+        }
+        // Never installed, so there is no source to install it from.
+        let entry = match index.get(&dep.name) {
+            Some(Value::Table(entry)) => entry,
+            _ => return Err(unmet_error(&vec![dep.clone()]))
+        };
+        printinfo!("\nInstalling {} from its source, required by {}...", dep.name, cmd.name);
+        let source_dir = updater::resolve_source(&dep.name, entry, do_dry_run)?;
+        let path = source_dir.path.join(updater::charon_file(&dep.name, entry));
+        let source = entry.get("source").and_then(|source| source.as_str()).and_then(updater::source_override);
+        let index = install_with_deps(&path, &InstallOpts { source, name: Some(dep.name.to_string()), ..opts.clone() }, do_dry_run, &visiting)?;
+        print_modified_files(&index.modified_files());
+    }
+
+    // Dry runs leave the main index untouched.
+    if do_dry_run {
+        return Ok(());
+    }
+    let unmet = dependencies::unmet(&cmd.depends, &main_index::load_main_index(false)?);
+    if !unmet.is_empty() {
+        return Err(unmet_error(&unmet));
+    }
+    return Ok(());
+}
+
+fn stage_installation(cmd: &mut InstallationCmd, old_index: &UtilIndex, conflicts: &[(String, PathBuf)], transaction: &mut Transaction, do_dry_run: bool) -> Result<UtilIndex, CharonIoError> {
+    //! Every change to the filesystem goes through transaction.
+    //! Conflicting files are removed from their owner's util index.
+    let util_name = cmd.name.clone();
+
+    // Install files.
+    let mut new_charon_index = copy_files(cmd, old_index, transaction, do_dry_run)?;
+    keep_modified_orphans(old_index, &mut new_charon_index);
+    new_charon_index.hooks = cmd.hooks.clone();
+    take_ownership(conflicts, transaction, do_dry_run)?;
+
     // Write (new) index.
     let charon_index_path = if do_dry_run {
         PathBuf::from(format!("{util_name}.dryrun.charon"))
@@ -112,50 +369,150 @@ pub fn install(path: &PathBuf, do_dry_run: bool) -> Result<(), CharonIoError> {
 
 
     println!("\nUpdating util index file: {charon_index_path:?}");
-    fs::write(&charon_index_path, new_charon_index.join("\n"))?;
+    transaction.write(&charon_index_path, &new_charon_index.to_toml_string()?)?;
 
     println!("\nUpdating main index file");
-    main_index::update(&mut cmd, do_dry_run)?;
+    transaction.protect(&main_index::get_main_index_path(do_dry_run)?)?;
+    main_index::update(cmd, do_dry_run)?;
 
-    return Ok(());
+    return Ok(new_charon_index);
+}
+
+
+fn find_conflicts(cmd: &InstallationCmd, do_dry_run: bool) -> Result<Vec<(String, PathBuf)>, CharonIoError> {
+    //! Every dest of cmd found in another util's index, paired with that util.
+    let mut conflicts: Vec<(String, PathBuf)> = Vec::new();
+    for (util, index) in util_index::load_all(&get_util_index_path(do_dry_run)?)? {
+        if util == cmd.name {
+            continue;
+        }
+        for item in &cmd.items {
+            if index.get_file(&item.dest).is_some() {
+                conflicts.push((util.to_string(), item.dest.to_owned()));
+            }
+        }
+    }
+    return Ok(conflicts);
 }
 
+fn take_ownership(conflicts: &[(String, PathBuf)], transaction: &mut Transaction, do_dry_run: bool) -> Result<(), CharonIoError> {
+    //! Removes conflicting files from their owner's util index, so uninstalling the owner leaves them alone.
+    let mut owners: Vec<&String> = conflicts.iter().map(|(owner, _)| owner).collect();
+    owners.dedup();
+    for owner in owners {
+        let dests: Vec<&PathBuf> = conflicts.iter().filter(|(util, _)| util == owner).map(|(_, dest)| dest).collect();
+        for dest in &dests {
+            printinfo!("Taking ownership of {dest:?} from {owner}.");
+        }
+        if do_dry_run {
+            continue;
+        }
 
-fn copy_files(cmd: &mut InstallationCmd, do_dry_run: bool) -> Vec<String> {
-    let mut charon_index: Vec<String> = Vec::new();
+        let path = get_util_index_path(do_dry_run)?.join(format!("{owner}.charon"));
+        let mut index = UtilIndex::load(&path)?;
+        index.files.retain(|file| !dests.contains(&&file.dest));
+        transaction.write(&path, &index.to_toml_string()?)?;
+    }
+    return Ok(());
+}
+
+fn copy_files(cmd: &mut InstallationCmd, old_index: &UtilIndex, transaction: &mut Transaction, do_dry_run: bool) -> Result<UtilIndex, CharonIoError> {
+    //! Stops at the first fatal error. The caller is responsible for rolling back the transaction.
+    //! Files modified since the last installation are kept, the new version is installed next to them.
+    let mut charon_index = UtilIndex::new();
 
-    charon_index.push("# Directories".to_string());
     for dir in &cmd.mkdirs {
-        charon_index.push(dir.to_string_lossy().to_string());
+        let existed = dir.exists();
+        charon_index.dirs.push(DirRecord { path: dir.to_owned(), existed });
 
-        if dir.exists() {
+        if existed {
             printinfo!("Directory already exists: {dir:?}");
-            charon_index.push("# Directory already exists: {dir:?}".into());
             continue;
         } 
 
         printinfo!("Created directory: {dir:?}");
 
         if !do_dry_run {
-            if let Err(err) = fs::create_dir_all(&dir) {
+            if let Err(err) = transaction.create_dir_all(&dir) {
                 printerror!("An error occurred while trying to make directory. Error = {err}.");
+                return Err(CharonIoError::GenericIoError(err));
             }
         }
     }
 
-    charon_index.push("# Files".to_string());
     for item in &mut cmd.items {
-        printinfo!("Installing {:?} --> {:?}", item.target, item.dest);
+        let modified = match old_index.get_file(&item.dest) {
+            Some(record) if is_locally_modified(item, record) => Some(item.dest.to_owned()),
+            _ => None
+        };
+        if let Some(dest) = &modified {
+            item.dest = util_index::charon_new_path(dest);
+            printinfo!("{dest:?} was modified since it was installed. Keeping it, new version goes to {:?}", item.dest);
+        }
 
-        if let Err(err) = item.try_install(do_dry_run) {
+        printinfo!("Installing {:?} --> {:?}", item.target, item.dest);
+        // Existed means before charon first installed dest, not before this update.
+        // Don't follow links, a dangling link still needs replacing.
+        let existed = match old_index.get_file(&item.dest) {
+            Some(record) => record.existed,
+            None => fs::symlink_metadata(&item.dest).is_ok()
+        };
+
+        if let Err(err) = item.try_install(transaction, do_dry_run) {
             printerror!("{err}");
+            if err.is_fatal() {
+                return Err(CharonIoError::InstallFailed(item.dest.to_owned(), err));
+            }
         }
         printinfo!("{}", item.comment);
-        charon_index.push(item.print_dest());
-        charon_index.push(item.comment.clone());
+        charon_index.files.push(FileRecord::from_item(item, existed, do_dry_run));
+
+        if let Some(dest) = modified {
+            // Remember the new version's checksum, so the file is compared against it next time.
+            let mut record = FileRecord::from_item(item, true, do_dry_run);
+            record.comment = format!("# Modified locally. New version installed to {:?}", item.dest);
+            record.dest = dest;
+            record.modified = true;
+            charon_index.files.push(record);
+        }
+    }
+    return Ok(charon_index);
+}
 
+fn is_locally_modified(item: &InstallItem, record: &FileRecord) -> bool {
+    //! True if dest changed since charon installed it, and the new version would overwrite those changes.
+    if item.link || record.link || !item.overwrite {
+        return false;
+    }
+    let installed = match &record.checksum {
+        Some(checksum) => checksum,
+        None => return false
+    };
+    let current = match util_index::file_checksum(&item.dest) {
+        Ok(checksum) => checksum,
+        Err(_) => return false
+    };
+    if &current == installed {
+        return false;
+    }
+    return item.checksum().map_or(true, |new| new != current);
+}
+
+pub fn print_modified_files(modified: &[PathBuf]) {
+    //! Install leaves this to its caller, so updating several utils prints every file once, at the end.
+    if modified.is_empty() {
+        return;
+    }
+    printinfo!("\nThe following files were modified locally, and were not overwritten:");
+    for file in modified {
+        // Orphans have no new version.
+        let new = util_index::charon_new_path(file);
+        if new.exists() {
+            printinfo!("{file:?}\tNew version: {new:?}");
+        } else {
+            printinfo!("{file:?}");
+        }
     }
-    return charon_index;
 }
 
 fn get_util_index_path(do_dry_run: bool) -> Result<PathBuf, CharonIoError> {
@@ -179,42 +536,60 @@ fn get_util_index_path(do_dry_run: bool) -> Result<PathBuf, CharonIoError> {
     return Ok(path);
 }
 
-fn read_util_index(util_name: &str, do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
+fn read_util_index(util_name: &str, do_dry_run: bool) -> Result<UtilIndex, CharonIoError> {
     //! Read file inside $MYTHOS_DATA_DIR/charon/$util_name.charon
+    //! Legacy (newline separated) index files are converted on read.
     // make_dir works the same as get_path, except it creates the dir if it dne.
     let mut path = get_util_index_path(do_dry_run)?;     
 
     path.push(util_name.to_owned() + ".charon");
 
-    if !path.exists() {
-        return Ok(vec![]);
-    }
+    return UtilIndex::load(&path);
+}
 
-    let contents: Vec<String> = fs::read_to_string(&path)?
-        .trim()
-        .split("\n")
-        .filter(|x| x.len() > 0)
-        .map(|x| x.to_string())
-        .collect();
+fn keep_modified_orphans(old_index: &UtilIndex, new_index: &mut UtilIndex) {
+    //! Orphans the user modified are never removed. They stay in the new index, marked as modified.
+    for old in &old_index.files {
+        if new_index.get_file(&old.dest).is_some() || !is_modified_orphan(old) {
+            continue;
+        }
+        printwarn!("{:?} is no longer installed, but was modified locally. Keeping it.", old.dest);
+        let mut record = old.clone();
+        record.modified = true;
+        record.comment = "# Modified locally. No longer installed, kept instead of removed.".into();
+        new_index.files.push(record);
+    }
+}
 
-    return Ok(contents);
+fn is_modified_orphan(old: &FileRecord) -> bool {
+    if old.modified {
+        return fs::symlink_metadata(&old.dest).is_ok();
+    }
+    if old.link {
+        return false;
+    }
+    return match (&old.checksum, util_index::file_checksum(&old.dest)) {
+        (Some(expected), Ok(checksum)) => &checksum != expected,
+        _ => false
+    };
 }
 
-fn process_orphans(old_index: Vec<String>, new_index: &Vec<String>, do_dry_run: bool) -> Vec<PathBuf> {
+fn process_orphans(old_index: UtilIndex, new_index: &UtilIndex, do_dry_run: bool) -> Vec<PathBuf> {
     // Compare files.
     // If file exists in old, but not in new, it is an orphan.
+    // Modified orphans were already kept in new, see keep_modified_orphans.
     let mut orphans: Vec<PathBuf> = Vec::new();
     printinfo!("\nProcessing orphans...");
 
-    for old in old_index {
-        // Skip comments.
-        if old.starts_with("#") { continue; }
-
-        if !new_index.contains(&old) {
-            let path = PathBuf::from(old);
+    for old in old_index.files {
+        if new_index.get_file(&old.dest).is_none() {
+            let kept = old.was_kept();
+            let path = old.dest;
             printinfo!("Found orphaned file: {path:?}");
 
-            if !path.exists() {
+            if kept {
+                printinfo!("But it existed before charon and was never overwritten. Skipping...");
+            } else if fs::symlink_metadata(&path).is_err() {
                 printinfo!("But file no longer exists. Skipping...");
             } else if do_dry_run {
                 printinfo!("Dry run. Skipping...");
@@ -234,15 +609,18 @@ fn process_orphans(old_index: Vec<String>, new_index: &Vec<String>, do_dry_run:
 
 
 fn uninstall<T: Iterator<Item = String>>(mut utils: T, mut do_dry_run: bool) {
+    //! --cascade also removes utils depending on the ones being removed.
     // Find corresponding charon files.
     // Delete files listed in charon files.
     // If any directories are completely empty, delete them too.
     // Remove utils from main index.
     let mut pkgs: Vec<String> = Vec::new();
+    let mut cascade = false;
     
     while let Some(util) = utils.next() {
         match util.as_str() {
             "-n" | "--dryrun" => do_dry_run = true,
+            "--cascade" => cascade = true,
             _ => {
                 if util.starts_with("-") {
                     continue;
@@ -251,7 +629,19 @@ fn uninstall<T: Iterator<Item = String>>(mut utils: T, mut do_dry_run: bool) {
             }
         }
     }
-    uninstaller::uninstall_utils(pkgs, do_dry_run);
+    if let Err(err) = validate_util_names(&pkgs) {
+        printerror!("{err}");
+        return;
+    }
+    uninstaller::uninstall_utils(pkgs, do_dry_run, cascade);
+}
+
+fn validate_util_names(utils: &[String]) -> Result<(), CharonIoError> {
+    //! Util names from the command line are joined to $MYTHOS_DATA_DIR/charon, so check them before anything is read.
+    for util in utils {
+        validate_util_name(util)?;
+    }
+    return Ok(());
 }
 
 #[cfg(test)]
@@ -273,12 +663,12 @@ mod tests {
     #[test]
     fn overwrite() {
         setup1();
-        let mut cmd = parse_installation_file(&PathBuf::from("tests/main/overwrite.charon")).unwrap();
-        let res = copy_files(&mut cmd, false);
+        let mut cmd = parse_installation_file(&PathBuf::from("tests/main/overwrite.charon"), &InstallOpts::default()).unwrap();
+        let res = copy_files(&mut cmd, &UtilIndex::new(), &mut Transaction::new(), false).unwrap();
 
         let mut counter = 0;
-        for item in res {
-            if item.contains("File exists && !overwrite") {
+        for file in res.files {
+            if file.comment.contains("File exists && !overwrite") {
                 counter += 1;
             }
         }
@@ -291,21 +681,21 @@ mod tests {
     fn load_old_index() {
         setup1();
         let res = read_util_index("util1", true).unwrap();
-        assert!(res.len() == 3);
+        assert!(res.files.len() == 3);
     }
     #[serial]
     #[test]
     fn read_old_index_dne() {
         let res = read_util_index("util2", true).unwrap();
-        assert!(res.is_empty());
+        assert!(res.files.is_empty());
     }
     #[serial]
     #[test]
     fn remove_orphans() {
         setup1();
         let old_index = read_util_index("orphan_test", true).unwrap();
-        let mut cmd = parse_installation_file(&PathBuf::from("tests/main/orphan_test.charon")).unwrap();
-        let new_index = copy_files(&mut cmd, true);
+        let mut cmd = parse_installation_file(&PathBuf::from("tests/main/orphan_test.charon"), &InstallOpts::default()).unwrap();
+        let new_index = copy_files(&mut cmd, &UtilIndex::new(), &mut Transaction::new(), true).unwrap();
 
         println!("{new_index:?}");
         println!("{old_index:?}");
@@ -317,5 +707,114 @@ mod tests {
         assert!(orphans.contains(&PathBuf::from("tests/main/dests/data/orphan_test/Orphan3/Item1")));
         assert_eq!(orphans.len(), 3);
     }
-}
+    #[test]
+    fn keep_modified_orphan() {
+        let modified = PathBuf::from("tests/modified/dests/etc/modified/app.conf");
+        let unchanged = PathBuf::from("tests/conflict/targets/shared.txt");
+        let mut old_index = UtilIndex::new();
+        let mut record = FileRecord::from_dest(modified.to_owned());
+        record.checksum = Some(util_index::checksum(b"original\n"));
+        old_index.files.push(record);
+        let mut record = FileRecord::from_dest(unchanged.to_owned());
+        record.checksum = util_index::file_checksum(&unchanged).ok();
+        old_index.files.push(record);
+
+        let mut new_index = UtilIndex::new();
+        keep_modified_orphans(&old_index, &mut new_index);
+        assert_eq!(new_index.modified_files(), vec![modified.to_owned()]);
+        assert_eq!(process_orphans(old_index, &new_index, true), vec![unchanged]);
+        assert!(modified.exists());
+    }
+    #[test]
+    fn cli_util_names() {
+        assert!(validate_util_names(&["util".into(), "other-util".into()]).is_ok());
+        for name in ["../../foo", "index", "", "a b"] {
+            let res = validate_util_names(&["util".into(), name.into()]);
+            assert!(matches!(res, Err(CharonIoError::InvalidUtilName(util)) if util == name));
+        }
+    }
+    #[serial]
+    #[test]
+    fn rollback_failed_install() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/transaction/dests/data");
+        }
+        let main_index = fs::read_to_string("tests/transaction/dests/data/charon/index.charon").unwrap();
+        let res = install(&PathBuf::from("tests/transaction/transaction.charon"), &InstallOpts::default(), false).unwrap_err();
+        println!("{res}");
+
+        assert!(matches!(res, CharonIoError::InstallFailed(_, _)));
+        assert!(!PathBuf::from("tests/transaction/dests/data/transaction/1.txt").exists());
+        assert!(PathBuf::from("tests/transaction/dests/data/transaction/conflict").is_dir());
+        assert!(!PathBuf::from("tests/transaction/dests/data/charon/transaction.charon").exists());
+        assert_eq!(fs::read_to_string("tests/transaction/dests/data/charon/index.charon").unwrap(), main_index);
+    }
+    #[serial]
+    #[test]
+    fn keep_modified_files() {
+        unsafe {
+            env::set_var("MYTHOS_CONFIG_DIR", "tests/modified/dests/etc");
+        }
+        let dest = PathBuf::from("tests/modified/dests/etc/modified/app.conf");
+        let mut old_index = UtilIndex::new();
+        let mut record = FileRecord::from_dest(dest.to_owned());
+        record.checksum = Some(util_index::checksum(b"original\n"));
+        old_index.files.push(record);
+
+        let mut cmd = parse_installation_file(&PathBuf::from("tests/modified/modified.charon"), &InstallOpts::default()).unwrap();
+        let new_index = copy_files(&mut cmd, &old_index, &mut Transaction::new(), true).unwrap();
+
+        let kept = new_index.get_file(&dest).unwrap();
+        assert!(kept.modified);
+        assert_eq!(new_index.modified_files(), vec![dest.to_owned()]);
+        assert_eq!(kept.checksum, Some(util_index::checksum(b"upstream\n")));
+        assert!(new_index.get_file(&PathBuf::from("tests/modified/dests/etc/modified/app.conf.charon-new")).is_some());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "edited by user\n");
+    }
+
+    #[serial]
+    #[test]
+    fn file_conflict() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/conflict/dests/data");
+        }
+        let dest = PathBuf::from("tests/conflict/dests/data/conflict/shared.txt");
+        let second = PathBuf::from("tests/conflict/dests/data/conflict/second.txt");
+        let cmd = parse_installation_file(&PathBuf::from("tests/conflict/conflict.charon"), &InstallOpts::default()).unwrap();
+        assert_eq!(find_conflicts(&cmd, true).unwrap(), vec![
+            ("other".to_string(), dest.to_owned()),
+            ("third".to_string(), second.to_owned()),
+        ]);
+
+        let res = install(&PathBuf::from("tests/conflict/conflict.charon"), &InstallOpts::default(), false).unwrap_err();
+        assert!(matches!(res, CharonIoError::FileConflict(conflicts) if conflicts == vec![
+            (dest.to_owned(), "other".to_string()),
+            (second.to_owned(), "third".to_string()),
+        ]));
+        assert!(!dest.exists());
+        assert!(!second.exists());
+    }
 
+    #[serial]
+    #[test]
+    fn unmet_dependencies() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/depends");
+        }
+        let cmd = parse_installation_file(&PathBuf::from("tests/depends/plugin.charon"), &InstallOpts::default()).unwrap();
+        let res = check_dependencies(&cmd, &InstallOpts::default(), false, &[]).unwrap_err();
+        assert!(matches!(res, CharonIoError::UnmetDependencies(util, deps) if util == "plugin" && deps == vec!["helper"]));
+
+        // helper was never installed, so there is no source to install it from.
+        let opts = InstallOpts { with_deps: true, ..InstallOpts::default() };
+        assert!(matches!(check_dependencies(&cmd, &opts, false, &[]), Err(CharonIoError::UnmetDependencies(_, _))));
+
+        // Installing host required cycle, which requires a newer host.
+        let cmd = parse_installation_file(&PathBuf::from("tests/depends/cycle.charon"), &InstallOpts::default()).unwrap();
+        let res = check_dependencies(&cmd, &opts, false, &["host".into()]).unwrap_err();
+        assert!(matches!(res, CharonIoError::DependencyCycle(cycle) if cycle == vec!["host", "cycle", "host"]));
+
+        let res = parse_installation_file(&PathBuf::from("tests/depends/invalid_depends.charon"), &InstallOpts::default()).unwrap_err();
+        assert!(matches!(res, CharonIoError::InvalidDependency(_)));
+    }
+}
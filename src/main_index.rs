@@ -1,4 +1,4 @@
-use std::fs;
+use std::{fs, path::PathBuf};
 use mythos_core::{printinfo, printwarn};
 use toml::{map::Map, Value};
 use crate::auto_installer::CharonIoError;
@@ -10,25 +10,39 @@ pub fn update(cmd: &InstallationCmd, do_dry_run: bool) -> Result<String, CharonI
     // Keep a master list of all util info, mostly their version and source.
     // This will be used to do system updates.
     let mut table = load_main_index(do_dry_run)?;
-    let info = get_info_from_cmd(&cmd);
+    let mut info = get_info_from_cmd(&cmd);
+
+    // Pins are set using --pin, and must survive reinstalls.
+    if let (Some(Value::Table(old)), Value::Table(new)) = (table.get(&cmd.name), &mut info) {
+        if let Some(pin) = old.get("pin") {
+            new.insert("pin".into(), pin.clone());
+        }
+    }
     table.insert(cmd.name.clone(), info);
 
-    // Write output
-    let output = match toml::to_string(&table) {
+    return save_main_index(&table, do_dry_run);
+}
+
+pub fn save_main_index(table: &Map<String, Value>, do_dry_run: bool) -> Result<String, CharonIoError> {
+    let output = match toml::to_string(table) {
         Ok(val) => val,
         Err(err) => return Err(CharonIoError::TomlSerError(err))
     };
 
+    let path = get_main_index_path(do_dry_run)?;
+    fs::write(path, &output)?;
+    
+    return Ok(output);
+}
+
+pub fn get_main_index_path(do_dry_run: bool) -> Result<PathBuf, CharonIoError> {
+    //! Path update() writes to.
+    //! Dry runs write to a separate file, so the real main index is left untouched.
     let root_path = crate::get_util_index_path(do_dry_run)?;
-    let path = root_path.join("index.charon");
     if do_dry_run {
-        let path = root_path.with_file_name("index.dry_run.charon");
-        fs::write(path, &output)?;
-    } else {
-        fs::write(path, &output)?;
+        return Ok(root_path.with_file_name("index.dry_run.charon"));
     }
-    
-    return Ok(output);
+    return Ok(root_path.join("index.charon"));
 }
 
 pub fn update_main_index(utils: Vec<String>) -> Result<String, CharonIoError> {
@@ -155,6 +169,18 @@ fn get_info_from_cmd(cmd: &InstallationCmd) -> Value {
     if let Some(source) = &cmd.source {
         output.insert("source".into(), Value::String(source.to_string()));
     }
+    if !cmd.depends.is_empty() {
+        let depends = cmd.depends.iter().map(|dep| Value::String(dep.raw.to_string())).collect();
+        output.insert("depends".into(), Value::Array(depends));
+    }
+    for (key, val) in [("branch", &cmd.branch), ("tag", &cmd.tag), ("rev", &cmd.rev), ("commit", &cmd.commit)] {
+        if let Some(val) = val {
+            output.insert(key.into(), Value::String(val.to_string()));
+        }
+    }
+    if let Some(charon_file) = &cmd.charon_file {
+        output.insert("charon_file".into(), Value::String(charon_file.to_string()));
+    }
     if let Some(desc) = &cmd.description {
         output.insert("description".into(), Value::String(desc.to_string()));
     }
@@ -0,0 +1,177 @@
+/*!
+ * Builds distributable util bundles, which can be installed using `charon <name>-<version>.tar.gz`.
+ * Bundles contain the charon file and every target it installs, at their path relative to the charon file.
+ */
+
+use std::{fs, path::{Path, PathBuf}, process::Command};
+use mythos_core::{printinfo, printwarn};
+use toml::Value;
+use crate::{archive::TempDir, auto_installer::{find_charon_file, is_index_style, parse_installation_file, CharonIoError, InstallOpts}};
+
+pub fn pack(path: &PathBuf, out_dir: &Path, opts: &InstallOpts, do_dry_run: bool) -> Result<PathBuf, CharonIoError> {
+    //! Returns the path of the new archive. Dry runs only list what would have been packed.
+    //! Archives are reproducible: entries are sorted, and mtimes and ownership are fixed.
+    let charon_path = find_charon_file(path.to_path_buf())?.canonicalize()?;
+    let parent = charon_path.parent().unwrap_or(Path::new("/")).to_path_buf();
+    // Targets may need to be built first, unless --no-build was given.
+    let cmd = parse_installation_file(&charon_path, &InstallOpts { dry_run: do_dry_run, ..opts.clone() })?;
+
+    let name = match &cmd.version {
+        Some(version) => format!("{}-{version}.tar.gz", cmd.name),
+        None => {
+            printwarn!("{} has no version in its info section.", cmd.name);
+            format!("{}.tar.gz", cmd.name)
+        }
+    };
+
+    let mut targets: Vec<&PathBuf> = cmd.items.iter().map(|item| &item.target).collect();
+    targets.sort();
+    targets.dedup();
+    let mut files: Vec<(&PathBuf, &Path)> = Vec::new();
+    for target in targets {
+        match target.strip_prefix(&parent) {
+            Ok(relative) => files.push((target, relative)),
+            Err(_) => return Err(CharonIoError::TargetOutsideBundle(target.to_owned()))
+        };
+    }
+    let contents = relative_targets(&fs::read_to_string(&charon_path)?, &parent)?;
+
+    let archive = out_dir.join(name);
+    if do_dry_run {
+        for (_, relative) in &files {
+            printinfo!("Dry run: Would have packed {relative:?}");
+        }
+        printinfo!("\nDry run: Would have created {archive:?}");
+        return Ok(archive);
+    }
+
+    // Stage the bundle, so only files charon installs end up in the archive.
+    let staging = TempDir::new("charon-pack")?;
+    let file_name = charon_path.file_name().unwrap_or_default();
+    fs::write(staging.path.join(file_name), contents)?;
+
+    for (target, relative) in files {
+        let dest = staging.path.join(relative);
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(target, &dest)?;
+        printinfo!("Packing {relative:?}");
+    }
+
+    let output = Command::new("tar")
+        .args(["--sort=name", "--mtime=@0", "--owner=0", "--group=0", "--numeric-owner", "--format=gnu"])
+        .args(["--use-compress-program", "gzip -n", "-cf"])
+        .arg(&archive)
+        .arg("-C")
+        .arg(&staging.path)
+        .arg(".")
+        .output()?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(CharonIoError::ArchiveFailed(archive, msg));
+    }
+
+    printinfo!("\nCreated {archive:?}");
+    return Ok(archive);
+}
+
+fn relative_targets(contents: &str, parent: &Path) -> Result<String, CharonIoError> {
+    //! Absolute targets inside parent are rewritten relative to it, so the bundle can be unpacked anywhere.
+    //! Bundles only contain build output, so the build section is dropped.
+    //! The charon file is only reformatted if it had to be changed.
+    if is_index_style(contents) {
+        return Ok(relative_index_targets(contents, parent));
+    }
+    let mut table = match toml::from_str::<Value>(contents) {
+        Ok(Value::Table(table)) => table,
+        _ => return Ok(contents.to_string())
+    };
+
+    let mut changed = table.remove("build").is_some();
+    for (_, val) in table.iter_mut() {
+        let items = match val {
+            Value::Array(items) => items,
+            _ => continue
+        };
+        for item in items.iter_mut() {
+            if let Some(Value::String(target)) = item.get_mut("target") {
+                let relative = Path::new(target.as_str()).strip_prefix(parent).map(|path| path.to_string_lossy().to_string());
+                if let Ok(relative) = relative {
+                    *target = relative;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    if !changed {
+        return Ok(contents.to_string());
+    }
+    return match toml::to_string(&table) {
+        Ok(val) => Ok(val),
+        Err(err) => Err(CharonIoError::TomlSerError(err))
+    };
+}
+
+fn relative_index_targets(contents: &str, parent: &Path) -> String {
+    //! Same as relative_targets, for `target -> dest` lines. Every other line is kept as is.
+    let mut output = String::new();
+    for line in contents.lines() {
+        let relative = line.split_once("->").and_then(|(target, dest)| {
+            let relative = Path::new(target.trim()).strip_prefix(parent).ok()?;
+            return Some(format!("{} -> {}", relative.to_string_lossy(), dest.trim()));
+        });
+        output.push_str(&relative.unwrap_or(line.to_string()));
+        output.push('\n');
+    }
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+    use super::*;
+
+    #[serial]
+    #[test]
+    fn reproducible_bundle() {
+        let out = TempDir::new("charon-pack-test").unwrap();
+        let first = pack(&PathBuf::from("tests/pack"), &out.path, &InstallOpts::default(), false).unwrap();
+        assert_eq!(first, out.path.join("pack-0.1.0.tar.gz"));
+        let first_bytes = fs::read(&first).unwrap();
+
+        fs::remove_file(&first).unwrap();
+        let second = pack(&PathBuf::from("tests/pack/pack.charon"), &out.path, &InstallOpts::default(), false).unwrap();
+        assert_eq!(first_bytes, fs::read(&second).unwrap());
+
+        let listing = Command::new("tar").arg("-tzf").arg(&second).output().unwrap();
+        let listing = String::from_utf8_lossy(&listing.stdout);
+        let mut files: Vec<&str> = listing.lines().filter(|line| !line.ends_with('/')).collect();
+        files.sort();
+        assert_eq!(files, vec!["./pack.charon", "./targets/a.txt", "./targets/sub/b.txt"]);
+    }
+
+    #[test]
+    fn dry_run_bundle() {
+        let out = TempDir::new("charon-pack-test").unwrap();
+        let archive = pack(&PathBuf::from("tests/pack"), &out.path, &InstallOpts::default(), true).unwrap();
+        assert_eq!(archive, out.path.join("pack-0.1.0.tar.gz"));
+        assert!(!archive.exists());
+    }
+
+    #[test]
+    fn rewrite_absolute_targets() {
+        let contents = "config = [ { target = \"/src/util/targets/a.txt\" }, { target = \"b.txt\" } ]\n";
+        let output = relative_targets(contents, Path::new("/src/util")).unwrap();
+        assert!(output.contains("target = \"targets/a.txt\""));
+        assert!(output.contains("target = \"b.txt\""));
+
+        let contents = "config = [ { target = \"b.txt\" } ]\n";
+        assert_eq!(relative_targets(contents, Path::new("/src/util")).unwrap(), contents);
+
+        let contents = "# Index style\n/src/util/targets/a.txt -> config\nb.txt -> bin/c\n";
+        let output = relative_targets(contents, Path::new("/src/util")).unwrap();
+        assert_eq!(output, "# Index style\ntargets/a.txt -> config\nb.txt -> bin/c\n");
+    }
+}
@@ -0,0 +1,125 @@
+/*!
+ * Looks up installed files in the util indexes.
+ * Used to find which util installed a file, and which files a util installed.
+ */
+
+use std::{env, path::{Path, PathBuf}};
+use mythos_core::{dirs::{get_path, MythosDir}, printinfo};
+use toml::Value;
+use crate::{auto_installer::CharonIoError, main_index, util_index::{self, FileRecord, UtilIndex}};
+
+pub fn find_owner(path: &Path) -> Result<bool, CharonIoError> {
+    //! Prints every util whose index contains path, with its version and the target path was installed from.
+    //! Returns false if no util owns path.
+    let root_path = match get_path(MythosDir::Data, "charon") {
+        Some(path) => path,
+        None => {
+            printinfo!("Could not find any installed utilities");
+            return Ok(false);
+        }
+    };
+    let path = absolute(path);
+    let main_index = main_index::load_main_index(false)?;
+
+    let mut found = false;
+    for (util, index) in util_index::load_all(&root_path)? {
+        for file in index.files.iter().filter(|file| is_same_file(&file.dest, &path)) {
+            let version = match main_index.get(&util).and_then(|entry| entry.get("version")) {
+                Some(Value::String(version)) => format!(" v{version}"),
+                _ => String::new()
+            };
+            printinfo!("{:?} is owned by {util}{version}, installed from {:?}", file.dest, file.target);
+            found = true;
+        }
+    }
+    if !found {
+        printinfo!("{path:?} was not installed by charon.");
+    }
+    return Ok(found);
+}
+
+pub fn list_files(util: &str) -> Result<Vec<String>, CharonIoError> {
+    //! Prints every file and created directory recorded in util's index.
+    //! Returns the printed lines.
+    let path = match get_path(MythosDir::Data, "charon") {
+        Some(path) => path.join(format!("{util}.charon")),
+        None => PathBuf::new()
+    };
+    if !path.is_file() {
+        return Err(CharonIoError::UnknownUtilName(Some(util.to_string())));
+    }
+    let index = UtilIndex::load(&path)?;
+
+    let mut output: Vec<String> = index.created_dirs().iter().map(|dir| format!("{dir:?}")).collect();
+    output.extend(index.files.iter().map(describe));
+    for line in &output {
+        printinfo!("{line}");
+    }
+    return Ok(output);
+}
+
+fn describe(file: &FileRecord) -> String {
+    if file.modified {
+        return format!("{:?}\t(modified locally)", file.dest);
+    }
+    return format!("{:?}", file.dest);
+}
+
+fn is_same_file(dest: &Path, path: &Path) -> bool {
+    //! Path is absolute, dest is compared as recorded, then made absolute.
+    if dest == path {
+        return true;
+    }
+    return absolute(dest) == path;
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    //! Canonicalizes the parent of path, but not path itself, since links must not resolve to their target.
+    //! Dest may have been removed since it was installed, so path is joined to $CWD if that fails.
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new(".")
+    };
+    return match (parent.canonicalize(), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => env::current_dir().map_or(path.to_path_buf(), |cwd| cwd.join(path))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::unix::fs::symlink, path::PathBuf};
+    use serial_test::serial;
+    use super::{absolute, find_owner, is_same_file, list_files};
+    use crate::{archive::TempDir, auto_installer::CharonIoError};
+
+    #[serial]
+    #[test]
+    fn owner_lookup() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/conflict/dests/data");
+        }
+        assert!(find_owner(&PathBuf::from("tests/conflict/dests/data/conflict/shared.txt")).unwrap());
+        assert!(find_owner(&env::current_dir().unwrap().join("tests/conflict/dests/data/conflict/shared.txt")).unwrap());
+        assert!(!find_owner(&PathBuf::from("tests/conflict/conflict.charon")).unwrap());
+
+        assert_eq!(list_files("other").unwrap(), vec!["\"tests/conflict/dests/data/conflict/shared.txt\""]);
+        assert!(matches!(list_files("missing"), Err(CharonIoError::UnknownUtilName(_))));
+    }
+
+    #[test]
+    fn same_file() {
+        let path = PathBuf::from("tests/conflict/conflict.charon");
+        assert!(is_same_file(&path, &path.canonicalize().unwrap()));
+        assert!(!is_same_file(&path, &PathBuf::from("tests/conflict")));
+        assert!(is_same_file(&PathBuf::from("tests/conflict/missing.txt"), &env::current_dir().unwrap().join("tests/conflict/missing.txt")));
+
+        // A link is its own file, not the file it points to.
+        let dir = TempDir::new("charon-query-test").unwrap();
+        let link = dir.path.join("link.charon");
+        symlink(path.canonicalize().unwrap(), &link).unwrap();
+        assert!(is_same_file(&link, &absolute(&link)));
+        assert!(!is_same_file(&path, &absolute(&link)));
+        assert!(!is_same_file(&link, &absolute(&path)));
+    }
+}
@@ -0,0 +1,190 @@
+/*!
+ * Reinstalls files that went missing or were modified since installation.
+ * Files that still match their util index are left untouched.
+ */
+
+use std::path::PathBuf;
+use mythos_core::{printerror, printinfo, printwarn};
+use toml::{map::Map, Value};
+use crate::{
+    auto_installer::{parse_installation_file, CharonIoError, InstallOpts, InstallationCmd, Transaction},
+    main_index,
+    updater::{self, SourceDir},
+    util_index::{FileRecord, UtilIndex},
+    verifier::{self, Drift},
+};
+
+pub fn repair_util(util: &str, opts: &InstallOpts, do_dry_run: bool) -> Result<Vec<PathBuf>, CharonIoError> {
+    //! Returns every file that was (or would have been) reinstalled.
+    //! Refuses to repair from a source at another version than the installed one, unless --force was given.
+    let main_index = main_index::load_main_index(do_dry_run)?;
+    let entry = match main_index.get(util) {
+        Some(Value::Table(entry)) => entry,
+        _ => return Err(CharonIoError::UnknownUtilName(Some(util.to_string())))
+    };
+    let (source, charon_file) = get_source(util, entry, do_dry_run)?;
+    let index_path = crate::get_util_index_path(do_dry_run)?.join(format!("{util}.charon"));
+    if !index_path.exists() {
+        return Err(CharonIoError::UnknownUtilName(Some(util.to_string())));
+    }
+    let mut index = UtilIndex::load(&index_path)?;
+    let opts = InstallOpts { name: Some(util.to_string()), dry_run: do_dry_run, ..opts.clone() };
+    let mut cmd = parse_installation_file(&source.path.join(charon_file), &opts)?;
+    if !opts.force {
+        check_version(util, entry, cmd.version.as_ref())?;
+    }
+
+    printinfo!("\nRepairing {util}...");
+    let mut transaction = Transaction::new();
+    let repaired = match stage_repair(&mut cmd, &mut index, &index_path, &mut transaction, do_dry_run) {
+        Ok(repaired) => repaired,
+        Err(err) => {
+            transaction.rollback();
+            return Err(err);
+        }
+    };
+    transaction.commit();
+
+    if repaired.is_empty() {
+        printinfo!("Nothing to repair, {util} matches its index.");
+    } else {
+        printinfo!("\nRepaired {} file(s):", repaired.len());
+        for path in &repaired {
+            printinfo!("{path:?}");
+        }
+    }
+    return Ok(repaired);
+}
+
+fn get_source(util: &str, entry: &Map<String, Value>, do_dry_run: bool) -> Result<(SourceDir, PathBuf), CharonIoError> {
+    //! Source directory, and the name of the charon file inside it.
+    //! Git sources are checked out at the commit the util was installed from, not at the latest one.
+    let mut entry = entry.clone();
+    if let Some(commit) = entry.get("commit").cloned() {
+        entry.insert("rev".into(), commit);
+    }
+    return Ok((updater::resolve_source(util, &entry, do_dry_run)?, updater::charon_file(util, &entry)));
+}
+
+fn check_version(util: &str, entry: &Map<String, Value>, source_version: Option<&String>) -> Result<(), CharonIoError> {
+    //! Repairing from another version would leave a mix of old and new files.
+    let installed = entry.get("version").and_then(|version| version.as_str());
+    if installed == source_version.map(|version| version.as_str()) {
+        return Ok(());
+    }
+    return Err(CharonIoError::SourceVersionMismatch(
+        util.to_string(),
+        installed.unwrap_or("unknown").to_string(),
+        source_version.map_or("unknown".into(), |version| version.to_string()),
+    ));
+}
+
+fn stage_repair(cmd: &mut InstallationCmd, index: &mut UtilIndex, index_path: &PathBuf, transaction: &mut Transaction, do_dry_run: bool) -> Result<Vec<PathBuf>, CharonIoError> {
+    let repaired = repair_files(cmd, index, transaction, do_dry_run)?;
+    if !repaired.is_empty() && !do_dry_run {
+        transaction.write(index_path, &index.to_toml_string()?)?;
+    }
+    return Ok(repaired);
+}
+
+fn repair_files(cmd: &mut InstallationCmd, index: &mut UtilIndex, transaction: &mut Transaction, do_dry_run: bool) -> Result<Vec<PathBuf>, CharonIoError> {
+    //! Only files listed in the util index are repaired. Anything new in the charon file needs an update instead.
+    //! Permission changes are fixed by reinstalling the file as well.
+    let mut output: Vec<PathBuf> = Vec::new();
+
+    for item in &mut cmd.items {
+        let record = match index.files.iter_mut().find(|file| file.dest == item.dest) {
+            Some(record) => record,
+            None => {
+                printwarn!("{:?} is not in the util index. Skipping...", item.dest);
+                continue;
+            }
+        };
+        // Kept on purpose, the installed version is at <dest>.charon-new.
+        if record.modified {
+            continue;
+        }
+        let drift = match verifier::verify_file(record) {
+            Some(drift) => drift,
+            None => continue
+        };
+
+        printinfo!("{drift}");
+        if let Drift::Missing(_) = drift {
+            if let Some(parent) = item.dest.parent() {
+                if !do_dry_run {
+                    transaction.create_dir_all(parent)?;
+                }
+            }
+        }
+
+        // Reinstall the file the same way it was installed.
+        item.overwrite = true;
+        item.force = true;
+        item.link = record.link && item.contents.is_none();
+        if let Err(err) = item.try_install(transaction, do_dry_run) {
+            printerror!("{err}");
+            if err.is_fatal() {
+                return Err(CharonIoError::InstallFailed(item.dest.to_owned(), err));
+            }
+        }
+        printinfo!("{}", item.comment);
+
+        if !do_dry_run {
+            let existed = record.existed;
+            *record = FileRecord::from_item(item, existed, do_dry_run);
+        }
+        output.push(item.dest.to_owned());
+    }
+    return Ok(output);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use serial_test::serial;
+    use crate::util_index;
+    use super::*;
+
+    #[serial]
+    #[test]
+    fn repair_drifted_files() {
+        unsafe {
+            env::set_var("MYTHOS_CONFIG_DIR", "tests/repair/dests/etc");
+        }
+        let mut cmd = parse_installation_file(&PathBuf::from("tests/repair/repair.charon"), &InstallOpts::default()).unwrap();
+        let mut index = UtilIndex::new();
+        for item in &cmd.items {
+            let mut record = FileRecord::from_dest(item.dest.to_owned());
+            record.checksum = util_index::file_checksum(&item.target).ok();
+            index.files.push(record);
+        }
+
+        let repaired = repair_files(&mut cmd, &mut index, &mut Transaction::new(), true).unwrap();
+        assert_eq!(repaired, vec![
+            PathBuf::from("tests/repair/dests/etc/repair/missing.txt"),
+            PathBuf::from("tests/repair/dests/etc/repair/modified.txt"),
+        ]);
+        assert!(!PathBuf::from("tests/repair/dests/etc/repair/missing.txt").exists());
+        assert_eq!(fs::read_to_string("tests/repair/dests/etc/repair/modified.txt").unwrap(), "edited\n");
+    }
+
+    #[serial]
+    #[test]
+    fn renamed_util_source() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_renamed");
+        }
+        let index = main_index::load_main_index(true).unwrap();
+        let entry = index.get("renamed").and_then(|entry| entry.as_table()).unwrap();
+        let (source, charon_file) = get_source("renamed", entry, true).unwrap();
+        assert_eq!(charon_file, PathBuf::from("tool.charon"));
+        assert!(source.path.join(&charon_file).is_file());
+
+        // The source moved on to 1.1.0 since renamed was installed.
+        let cmd = parse_installation_file(&source.path.join(charon_file), &InstallOpts::default()).unwrap();
+        let res = check_version("renamed", entry, cmd.version.as_ref()).unwrap_err();
+        assert!(matches!(res, CharonIoError::SourceVersionMismatch(_, installed, source) if installed == "1.0.0" && source == "1.1.0"));
+        assert!(check_version("renamed", entry, Some(&"1.0.0".to_string())).is_ok());
+    }
+}
@@ -1,13 +1,16 @@
 use std::{fs::{self}, path::PathBuf};
-use crate::main_index;
+use toml::{map::Map, Value};
+use crate::{auto_installer::Hooks, dependencies, main_index, util_index::UtilIndex};
 use mythos_core::{dirs::{get_path, MythosDir}, printerror, printinfo, printwarn};
 use crate::auto_installer::CharonIoError;
 
-pub fn uninstall_utils(utils: Vec<String>, do_dry_run: bool) {
+pub fn uninstall_utils(utils: Vec<String>, do_dry_run: bool, cascade: bool) {
     //! 1. Find corresponding charon files.
     //! 2. Delete files listed in charon files.
     //! 3. If any directories are completely empty, delete them too.
     //! 4. Remove utils from main index.
+    //! Utils whose pre_uninstall hook fails are left installed.
+    //! Utils other utils depend on are left installed, unless cascade is set.
     let root_path = match get_path(MythosDir::Data, "charon") {
         Some(mut path) => {
             // Using PathBuf.with_filename removes the last item of path, even if its a directory.
@@ -20,23 +23,49 @@ pub fn uninstall_utils(utils: Vec<String>, do_dry_run: bool) {
         }
     };
 
+    let main_index = main_index::load_main_index(false).unwrap_or_default();
+    let utils = check_dependents(utils, &main_index, cascade);
+
     // Find all files that should be deleted.
     let mut files: Vec<PathBuf> = Vec::new();
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut removed: Vec<(String, Option<String>, Hooks)> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
     for util in &utils {
-        let mut res = match find_files(
-                &util, 
-                &root_path.with_file_name(format!("{util}.charon"))) {
-            Ok(files) => files,
+        let path = root_path.with_file_name(format!("{util}.charon"));
+        let index = match load_util_index(&util, &path) {
+            Ok(index) => index,
             Err(err) => {
                 printwarn!("{err}");
                 continue;
             }
         };
-        files.append(&mut res);
+
+        let version = match main_index.get(util).and_then(|entry| entry.get("version")) {
+            Some(Value::String(version)) => Some(version.to_string()),
+            _ => None
+        };
+        if let Err(err) = index.hooks.run("pre_uninstall", util, version.as_ref(), do_dry_run) {
+            printerror!("{err} Not uninstalling {util}.");
+            skipped.push(util.to_string());
+            continue;
+        }
+
+        files.append(&mut find_files(&util, &index, &path));
+        dirs.append(&mut index.created_dirs());
+        removed.push((util.to_string(), version, index.hooks));
     }
 
     // Remove files and empty dirs.
     remove_files(files, do_dry_run);
+    remove_created_dirs(dirs, do_dry_run);
+
+    for (util, version, hooks) in &removed {
+        if let Err(err) = hooks.run("post_uninstall", util, version.as_ref(), do_dry_run) {
+            printwarn!("{err}");
+        }
+    }
+    let utils: Vec<String> = utils.into_iter().filter(|util| !skipped.contains(util)).collect();
 
     // Remove utils from main index.
     if !do_dry_run {
@@ -62,34 +91,97 @@ pub fn uninstall_utils(utils: Vec<String>, do_dry_run: bool) {
     }
 }
 
-fn find_files(util_name: &str, path: &PathBuf) -> Result<Vec<PathBuf>, CharonIoError> {
+fn check_dependents(utils: Vec<String>, main_index: &Map<String, Value>, cascade: bool) -> Vec<String> {
+    //! With cascade, every util depending on a removed util is removed too.
+    //! Otherwise, utils are only removed if nothing that stays installed depends on them.
+    let mut output = utils;
+    if cascade {
+        let mut i = 0;
+        while i < output.len() {
+            for dependent in dependencies::dependents(&output[i], main_index) {
+                if !output.contains(&dependent) {
+                    printinfo!("Also removing {dependent}, which depends on {}.", output[i]);
+                    output.push(dependent);
+                }
+            }
+            i += 1;
+        }
+        return output;
+    }
+
+    // Skipping a util may leave another util required, so repeat until nothing changes.
+    loop {
+        let mut keep: Vec<String> = Vec::new();
+        for util in &output {
+            let required_by: Vec<String> = dependencies::dependents(util, main_index)
+                .into_iter()
+                .filter(|dependent| !output.contains(dependent))
+                .collect();
+            if required_by.is_empty() {
+                keep.push(util.to_string());
+            } else {
+                printerror!("{}", CharonIoError::RequiredBy(util.to_string(), required_by));
+            }
+        }
+        if keep.len() == output.len() {
+            return output;
+        }
+        output = keep;
+    }
+}
+
+fn load_util_index(util_name: &str, path: &PathBuf) -> Result<UtilIndex, CharonIoError> {
     if !path.exists() {
         return Err(CharonIoError::UnknownUtilName(Some(util_name.to_string())));
     }
-    let file = match fs::read_to_string(path) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(CharonIoError::GenericIoError(err));
-        },
-    };
+    return UtilIndex::load(path);
+}
 
+fn find_files(util_name: &str, index: &UtilIndex, path: &PathBuf) -> Vec<PathBuf> {
+    //! Files listed in the util index, followed by the util index itself.
     let mut output: Vec<PathBuf> = Vec::new();
 
-    // Read contents of charon file.
-    for line in file.split("\n") {
-        if line.trim().starts_with("#") { continue; }
-        else if line.is_empty() { continue; }
-
-        let path = PathBuf::from(line);
-        if !path.exists() { 
-            printwarn!("{path:?} from {util_name} charon file does not exist.");
+    for file in &index.files {
+        if file.was_kept() {
+            printwarn!("{:?} existed before {util_name} was installed, and was never overwritten. Keeping it.", file.dest);
             continue;
         }
-        output.push(path);
+        // Links are removed themselves, so a dangling link still counts.
+        if fs::symlink_metadata(&file.dest).is_err() { 
+            printwarn!("{:?} from {util_name} charon file does not exist.", file.dest);
+            continue;
+        }
+        output.push(file.dest.to_owned());
     }
 
     output.push(path.to_path_buf());
-    return Ok(output);
+    return output;
+}
+
+fn remove_created_dirs(dirs: Vec<PathBuf>, do_dry_run: bool) -> Vec<PathBuf> {
+    //! Remove directories charon created during installation, as long as they are empty.
+    //! Nested directories are listed after their parents, so work backwards.
+    let mut output: Vec<PathBuf> = Vec::new();
+    for dir in dirs.iter().rev() {
+        let is_empty = match dir.read_dir() {
+            Ok(mut contents) => contents.next().is_none(),
+            Err(_) => continue
+        };
+        if !is_empty {
+            continue;
+        }
+
+        if do_dry_run {
+            printinfo!("Dry run: Would have removed dir {dir:?}.");
+        } else if let Err(err) = fs::remove_dir(&dir) {
+            printerror!("{err}");
+            continue;
+        } else {
+            printinfo!("Removing dir: {dir:?}.");
+        }
+        output.push(dir.to_owned());
+    }
+    return output;
 }
 
 fn remove_files(files: Vec<PathBuf>, do_dry_run: bool) -> Vec<PathBuf> {
@@ -169,7 +261,9 @@ mod tests {
     #[serial]
     #[test]
     fn test_find_charon_files() {
-        let files = find_files("a", &PathBuf::from("tests/uninstall/a.charon")).unwrap();
+        let path = PathBuf::from("tests/uninstall/a.charon");
+        let index = load_util_index("a", &path).unwrap();
+        let files = find_files("a", &index, &path);
         assert_eq!(files, vec![
             PathBuf::from("/bin"), 
             PathBuf::from("/home"),
@@ -177,6 +271,20 @@ mod tests {
         ]);
     }
 
+    #[serial]
+    #[test]
+    fn keep_existing_files() {
+        let path = PathBuf::from("tests/uninstall/kept.charon");
+        let index = load_util_index("kept", &path).unwrap();
+        let files = find_files("kept", &index, &path);
+        assert_eq!(files, vec![
+            PathBuf::from("tests/uninstall/kept/installed.txt"),
+            PathBuf::from("tests/uninstall/kept.charon"),
+        ]);
+        remove_files(files, true);
+        assert!(PathBuf::from("tests/uninstall/kept/user.txt").exists());
+    }
+
     #[serial]
     #[test]
     fn test_delete_files() {
@@ -211,6 +319,24 @@ mod tests {
         ]);
     }
 
+    #[serial]
+    #[test]
+    fn test_find_charon_files_dne() {
+        let res = load_util_index("dne", &PathBuf::from("tests/uninstall/dne.charon")).unwrap_err();
+        assert!(matches!(res, CharonIoError::UnknownUtilName(Some(_))));
+    }
+
+    #[test]
+    fn test_check_dependents() {
+        let index = "[host]\nversion = \"1.4.0\"\n\n[plugin]\ndepends = [\"host>=1.2\"]\n\n[theme]\ndepends = [\"plugin\"]\n";
+        let index: Map<String, Value> = toml::from_str(index).unwrap();
+
+        assert!(check_dependents(vec!["host".into()], &index, false).is_empty());
+        assert_eq!(check_dependents(vec!["host".into(), "plugin".into()], &index, false), Vec::<String>::new());
+        assert_eq!(check_dependents(vec!["theme".into(), "plugin".into()], &index, false), vec!["theme", "plugin"]);
+        assert_eq!(check_dependents(vec!["host".into()], &index, true), vec!["host", "plugin", "theme"]);
+    }
+
     #[serial]
     #[test]
     fn test_update_main_index() {
@@ -0,0 +1,163 @@
+/*!
+ * Git repositories used as util sources.
+ * Repositories are cloned to $MYTHOS_DATA_DIR/charon/sources/<util_name>, and checked out at the
+ * branch, tag or rev from the info section.
+ */
+
+use std::{path::{Path, PathBuf}, process::Command};
+use mythos_core::printinfo;
+use toml::{map::Map, Value};
+use crate::{archive::TempDir, auto_installer::CharonIoError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitRef {
+    /// Whatever the remote's HEAD points to.
+    Default,
+    Branch(String),
+    Tag(String),
+    Rev(String),
+}
+
+impl GitRef {
+    pub fn from_info(info: &Map<String, Value>) -> GitRef {
+        if let Some(Value::String(rev)) = info.get("rev") {
+            return GitRef::Rev(rev.to_string());
+        }
+        if let Some(Value::String(tag)) = info.get("tag") {
+            return GitRef::Tag(tag.to_string());
+        }
+        if let Some(Value::String(branch)) = info.get("branch") {
+            return GitRef::Branch(branch.to_string());
+        }
+        return GitRef::Default;
+    }
+
+    fn to_rev(&self) -> Result<String, CharonIoError> {
+        //! Refs starting with '-' would be read as an option by git checkout.
+        let rev = match self {
+            GitRef::Default => "origin/HEAD".into(),
+            GitRef::Branch(branch) => format!("origin/{branch}"),
+            GitRef::Tag(tag) => format!("refs/tags/{tag}"),
+            GitRef::Rev(rev) => rev.to_string(),
+        };
+        if rev.starts_with('-') {
+            return Err(CharonIoError::GitFailed("checkout".into(), format!("Invalid ref \"{rev}\", refs cannot start with '-'.")));
+        }
+        return Ok(rev);
+    }
+}
+
+pub fn is_git_source(source: &str) -> bool {
+    //! Urls (https://, ssh://, file://, git@host:repo) and bare repositories.
+    //! Any other source is a local directory containing a charon file.
+    if source.contains("://") || source.starts_with("git@") {
+        return true;
+    }
+    let path = Path::new(source);
+    return path.join("HEAD").is_file() && path.join("objects").is_dir();
+}
+
+pub fn fetch(util_name: &str, source: &str, git_ref: &GitRef) -> Result<PathBuf, CharonIoError> {
+    //! Returns the checkout directory.
+    let rev = git_ref.to_rev()?;
+    let path = crate::get_util_index_path(false)?.join("sources").join(util_name);
+    let checkout = path.to_string_lossy().to_string();
+
+    if path.join(".git").exists() {
+        printinfo!("Fetching {source} into {path:?}...");
+        git(&["-C", &checkout, "remote", "set-url", "--", "origin", source])?;
+        git(&["-C", &checkout, "fetch", "--quiet", "--tags", "--force", "origin"])?;
+        // origin/HEAD is only set by clone.
+        let _ = git(&["-C", &checkout, "remote", "set-head", "origin", "--auto"]);
+    } else {
+        printinfo!("Cloning {source} into {path:?}...");
+        git(&["clone", "--quiet", "--no-checkout", "--", source, &checkout])?;
+    }
+
+    git(&["-C", &checkout, "checkout", "--quiet", "--force", "--detach", &rev])?;
+    return Ok(path);
+}
+
+pub fn fetch_temp(source: &str, git_ref: &GitRef) -> Result<TempDir, CharonIoError> {
+    //! Like fetch, but clones to a temporary directory, leaving $MYTHOS_DATA_DIR untouched.
+    let rev = git_ref.to_rev()?;
+    let dir = TempDir::new("charon-git")?;
+    let checkout = dir.path.to_string_lossy().to_string();
+
+    printinfo!("Cloning {source} into {checkout:?}...");
+    git(&["clone", "--quiet", "--no-checkout", "--", source, &checkout])?;
+    git(&["-C", &checkout, "checkout", "--quiet", "--force", "--detach", &rev])?;
+    return Ok(dir);
+}
+
+pub fn head_commit(checkout: &Path) -> Option<String> {
+    //! Commit checked out at checkout, if it is a git repository.
+    let checkout = checkout.to_string_lossy().to_string();
+    return git(&["-C", &checkout, "rev-parse", "--verify", "HEAD"]).ok().map(|commit| commit.trim().to_string());
+}
+
+fn git(args: &[&str]) -> Result<String, CharonIoError> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(CharonIoError::GitFailed(args.join(" "), msg));
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use serial_test::serial;
+    use super::*;
+
+    fn commit(repo: &str, version: &str) {
+        fs::write(format!("{repo}/git.charon"), format!("info = {{ version = \"{version}\" }}\n")).unwrap();
+        git(&["-C", repo, "add", "."]).unwrap();
+        git(&["-C", repo, "-c", "user.name=charon", "-c", "user.email=charon@localhost", "commit", "--quiet", "-m", version]).unwrap();
+    }
+
+    #[serial]
+    #[test]
+    fn fetch_git_source() {
+        let root = env::temp_dir().join("charon_git_source");
+        let _ = fs::remove_dir_all(&root);
+        let repo = root.join("repo").to_string_lossy().to_string();
+        fs::create_dir_all(&repo).unwrap();
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", root.join("data"));
+        }
+
+        git(&["init", "--quiet", "--initial-branch=main", &repo]).unwrap();
+        commit(&repo, "0.1.0");
+        git(&["-C", &repo, "tag", "v0.1.0"]).unwrap();
+        commit(&repo, "0.2.0");
+
+        let source = format!("file://{repo}");
+        assert!(is_git_source(&source));
+        assert!(!is_git_source("tests/updater/sources/a"));
+
+        let checkout = fetch("git", &source, &GitRef::Default).unwrap();
+        assert!(fs::read_to_string(checkout.join("git.charon")).unwrap().contains("0.2.0"));
+
+        let checkout = fetch("git", &source, &GitRef::Tag("v0.1.0".into())).unwrap();
+        assert!(fs::read_to_string(checkout.join("git.charon")).unwrap().contains("0.1.0"));
+        let tagged = head_commit(&checkout).unwrap();
+        assert_eq!(tagged.len(), 40);
+
+        commit(&repo, "0.3.0");
+        let temp = fetch_temp(&source, &GitRef::Default).unwrap();
+        assert!(fs::read_to_string(temp.path.join("git.charon")).unwrap().contains("0.3.0"));
+        assert!(fs::read_to_string(checkout.join("git.charon")).unwrap().contains("0.1.0"));
+
+        let checkout = fetch("git", &source, &GitRef::Branch("main".into())).unwrap();
+        assert!(fs::read_to_string(checkout.join("git.charon")).unwrap().contains("0.3.0"));
+        let checkout = fetch("git", &source, &GitRef::Rev(tagged)).unwrap();
+        assert!(fs::read_to_string(checkout.join("git.charon")).unwrap().contains("0.1.0"));
+
+        let res = fetch("git", &source, &GitRef::Rev("--upload-pack=touch".into())).unwrap_err();
+        assert!(matches!(res, CharonIoError::GitFailed(_, _)));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,24 +1,34 @@
-use std::{fs, path::PathBuf};
+mod git_source;
+
+use std::{fs, path::{Path, PathBuf}};
 
 use mythos_core::{cli::get_user_permission, printerror, printinfo, printwarn};
 use toml::{map::Map, Value};
 
-use crate::{auto_installer::CharonIoError, main_index};
+use crate::{archive::{self, TempDir}, auto_installer::{CharonIoError, InstallOpts}, dependencies, main_index, version::{Version, VersionReq}};
+use git_source::GitRef;
 
 
-pub fn update(do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
+pub fn update(opts: &InstallOpts, do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
     //! Finds all mythos utils installed by charon and checks whether they should be updated.
-    //! Returns a list of all utils that are changed.
+    //! Returns a list of all utils that are changed, in the order they are updated.
+    //! Dependencies are updated before the utils depending on them.
+    //! Utils in a dependency cycle, and the utils depending on them, are skipped.
 
     // Load index.charon
     // Iterate over utils
     // If util has source, lo
     let index = main_index::load_main_index(do_dry_run)?;
+    let (order, blocked) = update_order(&index);
 
-    let mut output: Vec<String> = Vec::new();
-    let mut paths: Vec<PathBuf> = Vec::new();
-    for (name, val) in index {
+    let mut plan: Vec<String> = Vec::new();
+    let mut pinned: Vec<String> = Vec::new();
+    for (name, val) in &index {
         printinfo!("\nChecking updates for {name}...");
+        if blocked.contains(name) {
+            printwarn!("{name} is part of, or depends on, a dependency cycle. Skipping...");
+            continue;
+        }
         let info= match val.as_table() {
             Some(val) => val,
             None => {
@@ -39,8 +49,8 @@ pub fn update(do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
                 continue;
             }
         };
-        let source_path = match info.get("source") {
-            Some(Value::String(path)) => path,
+        match info.get("source") {
+            Some(Value::String(_)) => (),
             Some(val) => {
                 printwarn!("Could not parse source path. Expected string, found {}. Skipping...", val.type_str());
                 continue;
@@ -50,13 +60,19 @@ pub fn update(do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
                 continue;
             }
         };
+        let source_dir = match resolve_source(&name, info, do_dry_run) {
+            Ok(dir) => dir,
+            Err(err) => {
+                printwarn!("{err} Skipping...");
+                continue;
+            }
+        };
 
-        let path = format!("{source_path}/{name}.charon");
-        let local_charon = match load_local_charon(
-            &path) {
+        let path = source_dir.path.join(charon_file(&name, info));
+        let local_charon = match load_local_charon(&path) {
             Ok(l) => l,
             Err(err) => {
-                printinfo!("Error parsing charon file at {source_path}/{name}.charon. Error = {err}. Skipping...");
+                printinfo!("Error parsing charon file at {path:?}. Error = {err}. Skipping...");
                 continue;
             }
         };
@@ -88,21 +104,34 @@ pub fn update(do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
                 continue;
             }
 
+            if let Some(pin) = get_pin(&name, info) {
+                let allowed = Version::parse(local_version).is_some_and(|version| pin.1.matches(&version));
+                if !allowed {
+                    printinfo!("{name} is pinned to {}. Skipping v{local_version}...", pin.0);
+                    pinned.push(format!("{name}\t\t{}\t\tv{version} --> v{local_version}", pin.0));
+                    continue;
+                }
+            }
+
             if do_dry_run {
                 printinfo!("Updated {name} from v{version} --> v{local_version}");
             }             
-            output.push(name);
-            paths.push(path.into());
+            plan.push(name.to_string());
         }
     }
+    plan.sort_by_key(|name| order.iter().position(|util| util == name));
+    let output: Vec<String> = plan.clone();
     
     println!("---------------------------------");
+    if !pinned.is_empty() {
+        printinfo!("The following utils are pinned, and were not updated:\n{}", pinned.join("\n"));
+    }
 
     if do_dry_run {
         return Ok(output);
     }
 
-    if paths.len() == 0 {
+    if plan.len() == 0 {
         printinfo!("No updates found!");
         return Ok(output);
     }
@@ -110,11 +139,7 @@ pub fn update(do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
     let msg = output.join("\n");
     if get_user_permission(false, 
         &format!("The following utils will be updated: \n{msg}\n")) {
-        for path in paths {
-            if let Err(err) = run_update(&path) {
-                printerror!("{err}");
-            }
-        }
+        run_updates(plan, &index, opts);
         printinfo!("Update completed!");
     } else {
         printinfo!("Update cancelled...");
@@ -123,12 +148,17 @@ pub fn update(do_dry_run: bool) -> Result<Vec<String>, CharonIoError> {
 }
 
 
-pub fn force_update<T: Iterator<Item = String>>(utils: T, do_dry_run: bool) -> Result<(), CharonIoError> {
+pub fn force_update<T: Iterator<Item = String>>(utils: T, opts: &InstallOpts, do_dry_run: bool) -> Result<(), CharonIoError> {
     let index = main_index::load_main_index(do_dry_run)?;
+    let (order, blocked) = update_order(&index);
 
     let mut output: Vec<String> = Vec::new();
-    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut sources: Vec<String> = Vec::new();
     for util in utils {
+        if blocked.contains(&util) {
+            printwarn!("{util} is part of, or depends on, a dependency cycle. Skipping...");
+            continue;
+        }
         let entry = match index.get(&util) {
             Some(Value::Table(e)) => e,
             Some(p) => {
@@ -156,7 +186,7 @@ pub fn force_update<T: Iterator<Item = String>>(utils: T, do_dry_run: bool) -> R
         };
 
         output.push(util.to_string());
-        paths.push(PathBuf::from(path));
+        sources.push(path.to_string());
     }
 
     let mut i: isize = -1;
@@ -164,7 +194,7 @@ pub fn force_update<T: Iterator<Item = String>>(utils: T, do_dry_run: bool) -> R
         "Found source paths for the following source paths:\n{}\n\nWould you like to continue?",
         output.iter().map(|x| { 
             i += 1;
-            format!("{x}\t\t{:?}", paths[i as usize])
+            format!("{x}\t\t{}", sources[i as usize])
         }).collect::<Vec<String>>().join("\n")
     );
 
@@ -173,24 +203,145 @@ pub fn force_update<T: Iterator<Item = String>>(utils: T, do_dry_run: bool) -> R
         return Ok(());
     }
 
-    for (util, path) in output.iter().zip(paths) {
-        if do_dry_run {
+    let mut plan = output;
+    plan.sort_by_key(|name| order.iter().position(|util| util == name));
+
+    if do_dry_run {
+        for util in plan {
             printinfo!("Finished updating {util}!");
-            continue;
         }
+        return Ok(());
+    }
+    run_updates(plan, &index, opts);
+    return Ok(());
+}
 
-        match run_update(&path) {
-            Ok(_) => printinfo!("Finished updating {util}!"),
-            Err(err) => printerror!("Could not update {util}. Error = {err}")
-        }
+
+pub fn pin(arg: &str, do_dry_run: bool) -> Result<(), CharonIoError> {
+    //! arg is either util or util=version-req.
+    //! Without a version requirement, util is held at its installed version.
+    let mut index = main_index::load_main_index(do_dry_run)?;
+    let (util, req) = match arg.split_once('=') {
+        Some((util, req)) => (util.trim(), Some(req.trim())),
+        None => (arg.trim(), None),
+    };
+
+    let entry = match index.get_mut(util) {
+        Some(Value::Table(entry)) => entry,
+        _ => return Err(CharonIoError::UnknownUtilName(Some(util.to_string())))
+    };
+    let req = match (req, entry.get("version")) {
+        (Some(req), _) => req.to_string(),
+        (None, Some(Value::String(version))) => format!("={version}"),
+        (None, _) => return Err(CharonIoError::NoVersionToPin(util.to_string()))
+    };
+    if VersionReq::parse(&req).is_none() {
+        return Err(CharonIoError::InvalidVersionReq(req));
     }
+
+    entry.insert("pin".into(), Value::String(req.to_string()));
+    main_index::save_main_index(&index, do_dry_run)?;
+    printinfo!("Pinned {util} to {req}.");
     return Ok(());
 }
 
+pub fn unpin(util: &str, do_dry_run: bool) -> Result<(), CharonIoError> {
+    let mut index = main_index::load_main_index(do_dry_run)?;
+    let entry = match index.get_mut(util) {
+        Some(Value::Table(entry)) => entry,
+        _ => return Err(CharonIoError::UnknownUtilName(Some(util.to_string())))
+    };
+
+    if entry.remove("pin").is_none() {
+        printinfo!("{util} is not pinned.");
+        return Ok(());
+    }
+    main_index::save_main_index(&index, do_dry_run)?;
+    printinfo!("Unpinned {util}.");
+    return Ok(());
+}
 
-fn load_local_charon(root_path: &str) -> Result<Map<String, Value>, std::io::Error> {
-    let path = PathBuf::from(root_path);
-    let contents = fs::read_to_string(&path)?;    
+fn get_pin(util: &str, info: &Map<String, Value>) -> Option<(String, VersionReq)> {
+    //! Invalid pins are ignored with a warning, rather than blocking every update.
+    let pin = match info.get("pin") {
+        Some(Value::String(pin)) => pin,
+        _ => return None
+    };
+    return match VersionReq::parse(pin) {
+        Some(req) => Some((pin.to_string(), req)),
+        None => {
+            printwarn!("{}", CharonIoError::InvalidVersionReq(pin.to_string()));
+            printwarn!("Ignoring pin for {util}.");
+            None
+        }
+    };
+}
+
+/**
+ * Local directory containing a util's source charon file.
+ */
+#[derive(Debug)]
+pub struct SourceDir {
+    pub path: PathBuf,
+    /// Set if path is a temporary directory, which is removed once the source is dropped.
+    _temp: Option<TempDir>,
+}
+
+impl SourceDir {
+    fn temp(dir: TempDir) -> SourceDir {
+        return SourceDir { path: dir.path.to_owned(), _temp: Some(dir) };
+    }
+}
+
+pub fn resolve_source(util: &str, info: &Map<String, Value>, do_dry_run: bool) -> Result<SourceDir, CharonIoError> {
+    //! Git sources are fetched to $MYTHOS_DATA_DIR/charon/sources/<util> first, dry runs clone to a temporary directory instead.
+    //! Archives are always unpacked to a temporary directory, removed once the SourceDir is dropped.
+    let source = match info.get("source") {
+        Some(Value::String(source)) => source,
+        _ => return Err(CharonIoError::NoSourcePath(util.to_string()))
+    };
+    if git_source::is_git_source(source) {
+        if do_dry_run {
+            return Ok(SourceDir::temp(git_source::fetch_temp(source, &GitRef::from_info(info))?));
+        }
+        let path = git_source::fetch(util, source, &GitRef::from_info(info))?;
+        return Ok(SourceDir { path, _temp: None });
+    }
+    if archive::is_archive(Path::new(source)) {
+        return Ok(SourceDir::temp(archive::unpack_temp(Path::new(source))?));
+    }
+    return Ok(SourceDir { path: PathBuf::from(source), _temp: None });
+}
+
+pub fn source_commit(source: &str, dir: &Path) -> Option<String> {
+    //! Commit checked out in dir, if source is a git repository.
+    //! Recorded in the main index, so repairs use the same files the util was installed from.
+    if !git_source::is_git_source(source) {
+        return None;
+    }
+    return git_source::head_commit(dir);
+}
+
+pub fn charon_file(util: &str, info: &Map<String, Value>) -> PathBuf {
+    //! Name of the util's charon file inside its source.
+    //! Only differs from <util>.charon if the util was installed under another name.
+    return match info.get("charon_file").and_then(|file| file.as_str()) {
+        Some(file) => PathBuf::from(Path::new(file).file_name().unwrap_or_default()),
+        None => PathBuf::from(format!("{util}.charon"))
+    };
+}
+
+pub fn source_override(source: &str) -> Option<String> {
+    //! Git checkouts and unpacked archives live in the charon data dir,
+    //! but the main index should keep pointing at the repository or archive.
+    if git_source::is_git_source(source) || archive::is_archive(Path::new(source)) {
+        return Some(source.to_string());
+    }
+    return None;
+}
+
+fn load_local_charon(path: &PathBuf) -> Result<Map<String, Value>, std::io::Error> {
+    let contents = fs::read_to_string(path)?;    
     return match toml::from_str::<Value>(&contents) {
         Ok(Value::Table(table)) => Ok(table),
         _ => return Err(std::io::ErrorKind::InvalidData.into())
@@ -199,68 +350,112 @@ fn load_local_charon(root_path: &str) -> Result<Map<String, Value>, std::io::Err
 
 fn compare_versions(old: &str, new: &str) -> Option<bool> {
     //! Returns true if new > old.
-    let v1 = old.split(".");
-    let v2 = new.split(".");
-
-    // old = 0.0.1 new = 0.0.1 => false
-    // old = 0.0.2 new = 0.0.1 => false
-    // old = 0.0.1.1 new = 0.0.1 => false
-    // old = 0.0.2 new = 0.0.1.1 => false
-    //
-    // old = 0.0.1 new = 0.0.2 => true
-    // old = 0.0.1 new = 0.0.1.1 => true
-    // old = 0.0.1.1 new = 0.0.2 => true
-
-    // for (v1, v2) in (v1.next(), v2.next()){
-    //     if v2.parse::<i32>().ok()? > v1.parse::<i32>().ok()?{
-    //         return Some(true);
-    //     }
-    // }
-
-    for (v1, v2) in zip_uneven(v1, v2) {
-        if v2.is_none() {
-            return Some(false);
-        } else if v1.is_none() {
-            return Some(true);
+    //! Returns None if either version cannot be parsed.
+    let (old_version, new_version) = match (Version::parse(old), Version::parse(new)) {
+        (Some(old), Some(new)) => (old, new),
+        (old_version, _) => {
+            let bad = if old_version.is_none() { old } else { new };
+            printwarn!("Could not parse version \"{bad}\". Expected a version like 1.2.3, v1.2.0-rc.1 or 2024.01.15.");
+            return None;
         }
+    };
+    return Some(new_version > old_version);
+}
 
-        let num1 = v1.unwrap().parse::<i32>().ok()?;
-        let num2 = v2.unwrap().parse::<i32>().ok()?;
+fn update_order(index: &Map<String, Value>) -> (Vec<String>, Vec<String>) {
+    //! Every util comes after the utils it depends on, otherwise the main index's order is kept.
+    //! Dependencies which are not installed are ignored.
+    //! Also returns the utils which cannot be updated, because they are part of or depend on a dependency cycle.
+    let mut order: Vec<String> = Vec::new();
+    let mut visiting: Vec<String> = Vec::new();
+    let mut blocked: Vec<String> = Vec::new();
+    for name in index.keys() {
+        visit(name, index, &mut visiting, &mut order, &mut blocked);
+    }
 
-        if num1 == num2 { 
-            continue; 
-        } else if num1 > num2 {
-            return Some(false);
-        } else {
-            return Some(true);
+    // Dependencies come first, so one pass finds every util depending on a cycle.
+    for name in &order {
+        let entry = match index.get(name) {
+            Some(entry) => entry,
+            None => continue
+        };
+        if !blocked.contains(name) && dependencies::from_entry(entry).iter().any(|dep| blocked.contains(&dep.name)) {
+            blocked.push(name.to_string());
         }
     }
-
-    // Two versions are identical.
-    return Some(false);
+    return (order, blocked);
 }
 
-fn zip_uneven<T, U> (mut iter_1: U, mut iter_2: U) -> impl Iterator<Item = (Option<T>, Option<T>)> 
-where 
-    U: Iterator<Item = T> {
+fn visit(name: &str, index: &Map<String, Value>, visiting: &mut Vec<String>, order: &mut Vec<String>, blocked: &mut Vec<String>) {
+    //! Depth first, visiting holds the path from the util update_order started at.
+    //! Cycles are reported and their utils added to blocked, the rest of the index is still ordered.
+    if order.iter().any(|util| util == name) {
+        return;
+    }
+    if let Some(i) = visiting.iter().position(|util| util == name) {
+        let mut cycle = visiting[i..].to_vec();
+        cycle.push(name.to_string());
+        printerror!("{}", CharonIoError::DependencyCycle(cycle));
+        for util in &visiting[i..] {
+            if !blocked.contains(util) {
+                blocked.push(util.to_string());
+            }
+        }
+        return;
+    }
+    let entry = match index.get(name) {
+        Some(entry) => entry,
+        None => return
+    };
 
-    let mut output: Vec<(Option<T>, Option<T>)> = Vec::new();
-    loop {
-        let v1 = iter_1.next();
-        let v2 = iter_2.next();
+    visiting.push(name.to_string());
+    for dep in dependencies::from_entry(entry) {
+        visit(&dep.name, index, visiting, order, blocked);
+    }
+    visiting.pop();
+    order.push(name.to_string());
+}
 
-        if v1.is_none() && v2.is_none() {
-            break;
+fn run_updates(plan: Vec<String>, index: &Map<String, Value>, opts: &InstallOpts) {
+    //! Plan must be in update_order. Utils depending on a util which could not be updated are skipped.
+    let mut modified: Vec<PathBuf> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+    for util in plan {
+        let depends = index.get(&util).map(dependencies::from_entry).unwrap_or_default();
+        if let Some(dep) = depends.iter().find(|dep| failed.contains(&dep.name)) {
+            printwarn!("Skipping {util}, since its dependency {} could not be updated.", dep.name);
+            skipped.push(format!("{util}\t\t{} could not be updated", dep.name));
+            failed.push(util);
+            continue;
         }
-        output.push((v1, v2));
 
+        let info = match index.get(&util) {
+            Some(Value::Table(info)) => info,
+            _ => continue
+        };
+        match run_update(&util, info, opts, &mut modified) {
+            Ok(_) => printinfo!("Finished updating {util}!"),
+            Err(err) => {
+                printerror!("Could not update {util}. Error = {err}");
+                failed.push(util);
+            }
+        }
+    }
+    crate::print_modified_files(&modified);
+    if !skipped.is_empty() {
+        printinfo!("\nThe following utils were skipped:\n{}", skipped.join("\n"));
     }
-
-    return output.into_iter();
 }
 
-fn run_update(path: &PathBuf) -> Result<(), CharonIoError> {
-    crate::install(path, false)?;
+fn run_update(util: &str, info: &Map<String, Value>, opts: &InstallOpts, modified: &mut Vec<PathBuf>) -> Result<(), CharonIoError> {
+    //! Files which were modified locally, and so were not overwritten, are added to modified.
+    let source_dir = resolve_source(util, info, false)?;
+    let source = info.get("source").and_then(|source| source.as_str()).and_then(source_override);
+    // Keep the name the util was installed under, it may have been set using --name.
+    let opts = InstallOpts { source, name: Some(util.to_string()), ..opts.clone() };
+    let index = crate::install(&source_dir.path.join(charon_file(util, info)), &opts, false)?;
+    modified.extend(index.modified_files());
     return Ok(());
 }
 
@@ -268,7 +463,9 @@ fn run_update(path: &PathBuf) -> Result<(), CharonIoError> {
 mod tests {
     use std::env;
     use serial_test::serial;
-    use super::update;
+    use toml::{map::Map, Value};
+    use crate::auto_installer::InstallOpts;
+    use super::{compare_versions, update, update_order};
 
     #[serial]
     #[test]
@@ -277,18 +474,101 @@ mod tests {
             env::set_var("MYTHOS_DATA_DIR", "tests/updater");
         }
 
-        let output = update(true).unwrap();
+        let output = update(&InstallOpts::default(), true).unwrap();
         assert_eq!(output, vec!["a", "b"]);
     }
 
     #[serial]
     #[test]
     fn test_invalid_update() {
-        todo!()
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_invalid");
+        }
+
+        // e has an unparsable version, f's source is missing, g is a pre-release update.
+        let output = update(&InstallOpts::default(), true).unwrap();
+        assert_eq!(output, vec!["g"]);
+    }
+
+    #[serial]
+    #[test]
+    fn test_dependency_order() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_deps");
+        }
+
+        // app depends on lib, so lib is updated first.
+        let output = update(&InstallOpts::default(), true).unwrap();
+        assert_eq!(output, vec!["lib", "app"]);
+    }
+
+    #[serial]
+    #[test]
+    fn test_update_with_cycle() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_cycle");
+        }
+
+        // a and b depend on each other, c depends on a. Only d can be updated.
+        let output = update(&InstallOpts::default(), true).unwrap();
+        assert_eq!(output, vec!["d"]);
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        // e depends on the cycle, d and f are unrelated to it.
+        let index = "[a]\ndepends = [\"b\"]\n\n[b]\ndepends = [\"c>=1\"]\n\n[c]\ndepends = [\"a\"]\n\n[d]\n\n[e]\ndepends = [\"a\"]\n\n[f]\ndepends = [\"d\"]\n";
+        let index: Map<String, Value> = toml::from_str(index).unwrap();
+        let (order, blocked) = update_order(&index);
+        assert_eq!(order, vec!["c", "b", "a", "d", "e", "f"]);
+        assert_eq!(blocked, vec!["a", "b", "c", "e"]);
+
+        let index: Map<String, Value> = toml::from_str("[a]\ndepends = [\"b\", \"x\"]\n\n[b]\n").unwrap();
+        assert_eq!(update_order(&index), (vec!["b".to_string(), "a".to_string()], vec![]));
+    }
+
+    #[serial]
+    #[test]
+    fn test_renamed_update() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_renamed");
+        }
+
+        // Installed using --name renamed, from tool.charon.
+        let output = update(&InstallOpts::default(), true).unwrap();
+        assert_eq!(output, vec!["renamed"]);
+    }
+
+    #[serial]
+    #[test]
+    fn test_pinned_update() {
+        unsafe {
+            env::set_var("MYTHOS_DATA_DIR", "tests/updater_pinned");
+        }
+
+        // h is pinned to ^1, and 2.0.0 is available. i is pinned to ^1, and 1.1.0 is available.
+        let output = update(&InstallOpts::default(), true).unwrap();
+        assert_eq!(output, vec!["i"]);
     }
 
     #[test]
     fn test_compare_versions() {
-       todo!() 
+        assert_eq!(compare_versions("0.0.1", "0.0.1"), Some(false));
+        assert_eq!(compare_versions("0.0.2", "0.0.1"), Some(false));
+        assert_eq!(compare_versions("0.0.1.1", "0.0.1"), Some(false));
+        assert_eq!(compare_versions("0.0.2", "0.0.1.1"), Some(false));
+
+        assert_eq!(compare_versions("0.0.1", "0.0.2"), Some(true));
+        assert_eq!(compare_versions("0.0.1", "0.0.1.1"), Some(true));
+        assert_eq!(compare_versions("0.0.1.1", "0.0.2"), Some(true));
+
+        assert_eq!(compare_versions("1.2.0-rc1", "1.2.0"), Some(true));
+        assert_eq!(compare_versions("1.2.0", "1.2.0-rc1"), Some(false));
+        assert_eq!(compare_versions("v1.2", "1.2.0"), Some(false));
+        assert_eq!(compare_versions("1.2.0+a", "1.2.0+b"), Some(false));
+        assert_eq!(compare_versions("2024.01.15", "2024.02.01"), Some(true));
+
+        assert_eq!(compare_versions("latest", "1.0.0"), None);
+        assert_eq!(compare_versions("1.0.0", "1.0.0-"), None);
     }
 }
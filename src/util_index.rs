@@ -0,0 +1,455 @@
+/*!
+ * Util index files, saved to $MYTHOS_DATA_DIR/charon/<util_name>.charon.
+ * Records every file and directory charon installed for a util.
+ * Used to find orphans after an update, and to uninstall utils.
+ */
+
+use std::{fs, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use mythos_core::printwarn;
+use sha2::{Digest, Sha256};
+use toml::{map::Map, Value};
+
+use crate::auto_installer::{CharonIoError, Hooks, InstallItem};
+
+/// Schema version written by this version of charon.
+/// Legacy index files (newline separated list of paths) are read as version 1.
+pub const FORMAT_VERSION: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtilIndex {
+    /// Schema version the index was read as.
+    pub format_version: i64,
+    pub dirs: Vec<DirRecord>,
+    pub files: Vec<FileRecord>,
+    /// Hooks from the charon file, kept so they can be run on uninstall.
+    pub hooks: Hooks,
+}
+
+/**
+ * A directory charon was asked to create.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirRecord {
+    pub path: PathBuf,
+    /// Directory existed before installation.
+    pub existed: bool,
+}
+
+/**
+ * A single installed file.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    /// Path the file was installed to.
+    pub dest: PathBuf,
+    /// Path the file was installed from. Empty when read from a legacy index.
+    pub target: PathBuf,
+    /// Permissions applied to the installed file.
+    pub perms: Option<u32>,
+    /// Sha256 of the installed file's contents.
+    pub checksum: Option<String>,
+    /// Installation time, in seconds since the unix epoch.
+    pub installed: Option<i64>,
+    /// A file already existed at dest before installation.
+    pub existed: bool,
+    /// Dest is a symlink to target, rather than a copy.
+    pub link: bool,
+    /// Dest was modified by the user, so the new version was installed to <dest>.charon-new instead.
+    pub modified: bool,
+    /// Comments made during installation process.
+    pub comment: String,
+}
+
+impl UtilIndex {
+    pub fn new() -> UtilIndex {
+        return UtilIndex {
+            format_version: FORMAT_VERSION,
+            dirs: Vec::new(),
+            files: Vec::new(),
+            hooks: Hooks::default(),
+        };
+    }
+
+    pub fn load(path: &Path) -> Result<UtilIndex, CharonIoError> {
+        //! Returns an empty index if path does not exist.
+        if !path.exists() {
+            return Ok(UtilIndex::new());
+        }
+        let contents = fs::read_to_string(path)?;
+        return UtilIndex::parse(&contents);
+    }
+
+    pub fn parse(contents: &str) -> Result<UtilIndex, CharonIoError> {
+        //! Legacy index files never contain a format_version or [[files]].
+        //! Anything that does is a toml index, so it is an error if it cannot be read as one.
+        let is_toml = contents
+            .lines()
+            .map(|line| line.trim())
+            .any(|line| line.starts_with("format_version") || line == "[[files]]");
+        if !is_toml {
+            return Ok(UtilIndex::from_legacy(contents));
+        }
+
+        return match toml::from_str::<Value>(contents) {
+            Ok(Value::Table(table)) => UtilIndex::from_table(&table),
+            Ok(_) => Err(CharonIoError::InvalidCharonFile("Util index is not a toml table".into())),
+            Err(err) => Err(CharonIoError::TomlDeError(err)),
+        };
+    }
+
+    pub fn to_toml_string(&self) -> Result<String, CharonIoError> {
+        let mut table = Map::new();
+        table.insert("format_version".into(), Value::Integer(FORMAT_VERSION));
+        table.insert("dirs".into(), Value::Array(self.dirs.iter().map(|dir| dir.to_value()).collect()));
+        table.insert("files".into(), Value::Array(self.files.iter().map(|file| file.to_value()).collect()));
+        table.insert("hooks".into(), self.hooks.to_value());
+
+        return match toml::to_string(&table) {
+            Ok(val) => Ok(val),
+            Err(err) => Err(CharonIoError::TomlSerError(err))
+        };
+    }
+
+    pub fn get_file(&self, dest: &Path) -> Option<&FileRecord> {
+        return self.files.iter().find(|file| file.dest == dest);
+    }
+
+    pub fn modified_files(&self) -> Vec<PathBuf> {
+        //! Files which were modified locally, and so were not overwritten.
+        return self.files
+            .iter()
+            .filter(|file| file.modified)
+            .map(|file| file.dest.to_owned())
+            .collect();
+    }
+
+    pub fn created_dirs(&self) -> Vec<PathBuf> {
+        //! Directories which did not exist before charon created them.
+        return self.dirs
+            .iter()
+            .filter(|dir| !dir.existed)
+            .map(|dir| dir.path.to_owned())
+            .collect();
+    }
+
+    fn from_table(table: &Map<String, Value>) -> Result<UtilIndex, CharonIoError> {
+        let mut index = UtilIndex::new();
+        index.format_version = match table.get("format_version") {
+            Some(Value::Integer(val)) => *val,
+            _ => return Err(CharonIoError::InvalidCharonFile("Util index format_version must be an integer".into()))
+        };
+        if index.format_version > FORMAT_VERSION {
+            printwarn!("Util index was written by a newer version of charon (format v{}). Some fields may be ignored.", index.format_version);
+        }
+
+        if let Some(Value::Array(dirs)) = table.get("dirs") {
+            for dir in dirs {
+                index.dirs.push(DirRecord::from_value(dir)?);
+            }
+        }
+        if let Some(hooks) = table.get("hooks") {
+            index.hooks = Hooks::from_value(hooks);
+        }
+        if let Some(Value::Array(files)) = table.get("files") {
+            for file in files {
+                index.files.push(FileRecord::from_value(file)?);
+            }
+        }
+        return Ok(index);
+    }
+
+    fn from_legacy(contents: &str) -> UtilIndex {
+        //! Legacy index files are a newline separated list of paths.
+        //! Directories and files are separated by '# Directories' and '# Files' comments.
+        //! Any other comment describes the line before it.
+        let mut index = UtilIndex::new();
+        index.format_version = 1;
+
+        let mut in_dirs = false;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with("#") {
+                match line {
+                    "# Directories" => in_dirs = true,
+                    "# Files" => in_dirs = false,
+                    _ if in_dirs => {
+                        if let Some(dir) = index.dirs.last_mut() {
+                            dir.existed |= line.contains("Directory already exists");
+                        }
+                    },
+                    _ => {
+                        if let Some(file) = index.files.last_mut() {
+                            file.comment = line.to_string();
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if in_dirs {
+                index.dirs.push(DirRecord { path: line.into(), existed: false });
+            } else {
+                index.files.push(FileRecord::from_dest(line.into()));
+            }
+        }
+        return index;
+    }
+}
+
+impl DirRecord {
+    fn to_value(&self) -> Value {
+        let mut table = Map::new();
+        table.insert("path".into(), Value::String(self.path.to_string_lossy().to_string()));
+        table.insert("existed".into(), Value::Boolean(self.existed));
+        return Value::Table(table);
+    }
+
+    fn from_value(val: &Value) -> Result<DirRecord, CharonIoError> {
+        let path = match val.get("path") {
+            Some(Value::String(path)) => PathBuf::from(path),
+            _ => return Err(CharonIoError::InvalidCharonFile(format!("Directory record is missing a path: {val}")))
+        };
+        let existed = matches!(val.get("existed"), Some(Value::Boolean(true)));
+        return Ok(DirRecord { path, existed });
+    }
+}
+
+impl FileRecord {
+    pub fn from_dest(dest: PathBuf) -> FileRecord {
+        return FileRecord {
+            dest,
+            target: PathBuf::new(),
+            perms: None,
+            checksum: None,
+            installed: None,
+            existed: false,
+            link: false,
+            modified: false,
+            comment: String::new(),
+        };
+    }
+
+    pub fn from_item(item: &InstallItem, existed: bool, do_dry_run: bool) -> FileRecord {
+        //! Describe an item after try_install was called on it.
+        //! During a dry run, dest was never written, so describe the target instead.
+        //! Links have no checksum or permissions of their own.
+        let path = if do_dry_run || !item.dest.exists() {
+            &item.target
+        } else {
+            &item.dest
+        };
+
+        let perms = if item.link {
+            None
+        } else if do_dry_run {
+            item.expected_mode()
+        } else {
+            match fs::metadata(path) {
+                Ok(metadata) => Some(metadata.permissions().mode() & 0o7777),
+                Err(_) => None
+            }
+        };
+
+        // Links point at the canonical target, so record that instead.
+        let target = if item.link {
+            fs::canonicalize(&item.target).unwrap_or(item.target.to_owned())
+        } else {
+            item.target.to_owned()
+        };
+
+        return FileRecord {
+            dest: item.dest.to_owned(),
+            target,
+            perms,
+            checksum: match (&item.contents, item.link) {
+                (_, true) => None,
+                (Some(contents), _) => Some(checksum(contents.as_bytes())),
+                (None, _) => file_checksum(path).ok(),
+            },
+            installed: now(),
+            existed,
+            link: item.link,
+            modified: false,
+            comment: item.comment.to_owned(),
+        };
+    }
+
+    pub fn was_kept(&self) -> bool {
+        //! A file already existed at dest, and the item was not allowed to overwrite it.
+        //! Charon never wrote to it, so it must not be removed either.
+        return self.existed && self.comment.contains("File exists && !overwrite");
+    }
+
+    fn to_value(&self) -> Value {
+        let mut table = Map::new();
+        table.insert("dest".into(), Value::String(self.dest.to_string_lossy().to_string()));
+        table.insert("target".into(), Value::String(self.target.to_string_lossy().to_string()));
+        if let Some(perms) = self.perms {
+            table.insert("perms".into(), Value::String(format!("{perms:o}")));
+        }
+        if let Some(checksum) = &self.checksum {
+            table.insert("checksum".into(), Value::String(checksum.to_string()));
+        }
+        if let Some(installed) = self.installed {
+            table.insert("installed".into(), Value::Integer(installed));
+        }
+        table.insert("existed".into(), Value::Boolean(self.existed));
+        table.insert("link".into(), Value::Boolean(self.link));
+        table.insert("modified".into(), Value::Boolean(self.modified));
+        table.insert("comment".into(), Value::String(self.comment.to_string()));
+        return Value::Table(table);
+    }
+
+    fn from_value(val: &Value) -> Result<FileRecord, CharonIoError> {
+        let mut record = match val.get("dest") {
+            Some(Value::String(dest)) => FileRecord::from_dest(dest.into()),
+            _ => return Err(CharonIoError::InvalidCharonFile(format!("File record is missing a dest: {val}")))
+        };
+        if let Some(Value::String(target)) = val.get("target") {
+            record.target = target.into();
+        }
+        if let Some(Value::String(perms)) = val.get("perms") {
+            record.perms = u32::from_str_radix(perms, 8).ok();
+        }
+        if let Some(Value::String(checksum)) = val.get("checksum") {
+            record.checksum = Some(checksum.to_string());
+        }
+        if let Some(Value::Integer(installed)) = val.get("installed") {
+            record.installed = Some(*installed);
+        }
+        if let Some(Value::Boolean(existed)) = val.get("existed") {
+            record.existed = *existed;
+        }
+        if let Some(Value::Boolean(link)) = val.get("link") {
+            record.link = *link;
+        }
+        if let Some(Value::Boolean(modified)) = val.get("modified") {
+            record.modified = *modified;
+        }
+        if let Some(Value::String(comment)) = val.get("comment") {
+            record.comment = comment.to_string();
+        }
+        return Ok(record);
+    }
+}
+
+pub fn load_all(dir: &Path) -> Result<Vec<(String, UtilIndex)>, CharonIoError> {
+    //! Every util index inside dir, sorted by util name. The main index is skipped.
+    //! Index files which cannot be read are skipped with a warning.
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut output: Vec<(String, UtilIndex)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().map_or(true, |ext| ext != "charon") {
+            continue;
+        }
+        let util = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        if util == "index" {
+            continue;
+        }
+        match UtilIndex::load(&path) {
+            Ok(index) => output.push((util, index)),
+            Err(err) => printwarn!("Could not read util index {path:?}. Error = {err}")
+        }
+    }
+    output.sort_by(|a, b| a.0.cmp(&b.0));
+    return Ok(output);
+}
+
+pub fn charon_new_path(dest: &Path) -> PathBuf {
+    //! Where the new version of a locally modified file is installed.
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    return dest.with_file_name(format!("{name}.charon-new"));
+}
+
+pub fn file_checksum(path: &Path) -> Result<String, std::io::Error> {
+    let contents = fs::read(path)?;
+    return Ok(checksum(&contents));
+}
+
+pub fn checksum(contents: &[u8]) -> String {
+    //! Hex encoded sha256.
+    return Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+}
+
+fn now() -> Option<i64> {
+    return match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => Some(time.as_secs() as i64),
+        Err(_) => None
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_legacy_index() {
+        let contents = "# Directories\n/a\n# Directory already exists: {dir:?}\n/b\n# Files\n/a/1\n# ; Successfully installed\n/b/2\n# \n";
+        let index = UtilIndex::parse(contents).unwrap();
+
+        assert_eq!(index.format_version, 1);
+        assert_eq!(index.dirs, vec![
+            DirRecord { path: "/a".into(), existed: true },
+            DirRecord { path: "/b".into(), existed: false },
+        ]);
+        assert_eq!(index.created_dirs(), vec![PathBuf::from("/b")]);
+        assert_eq!(index.files.len(), 2);
+        assert_eq!(index.files[0].comment, "# ; Successfully installed");
+        assert!(index.get_file(Path::new("/b/2")).is_some());
+    }
+
+    #[test]
+    fn invalid_toml_index() {
+        let contents = "format_version = 2
+
+[[files]]
+dest = \"/a/1\"
+existed = tru
+";
+        assert!(matches!(UtilIndex::parse(contents), Err(CharonIoError::TomlDeError(_))));
+
+        let contents = "[[files]]
+dest = \"/a/1\"
+";
+        assert!(matches!(UtilIndex::parse(contents), Err(CharonIoError::InvalidCharonFile(_))));
+    }
+
+    #[test]
+    fn index_round_trip() {
+        let mut index = UtilIndex::new();
+        index.dirs.push(DirRecord { path: "/a".into(), existed: false });
+        index.files.push(FileRecord {
+            dest: "/a/1".into(),
+            target: "targets/1".into(),
+            perms: Some(0o755),
+            checksum: Some(checksum(b"")),
+            installed: Some(0),
+            existed: true,
+            link: false,
+            modified: false,
+            comment: "# ; Successfully installed".into(),
+        });
+        index.hooks.post_uninstall = Some("systemctl --user daemon-reload".into());
+
+        let contents = index.to_toml_string().unwrap();
+        println!("{contents}");
+        assert!(contents.contains("perms = \"755\""));
+        assert_eq!(UtilIndex::parse(&contents).unwrap(), index);
+    }
+
+    #[test]
+    fn sha256_checksum() {
+        assert_eq!(checksum(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
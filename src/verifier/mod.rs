@@ -0,0 +1,184 @@
+/*!
+ * Compares installed utils against their util index.
+ * Reports files that went missing, were modified or had their permissions changed since installation,
+ * as well as files charon did not install in directories it created.
+ */
+
+use std::{fmt, fs, os::unix::fs::PermissionsExt, path::PathBuf};
+use mythos_core::{dirs::{get_path, MythosDir}, printerror, printinfo, printwarn};
+use crate::{auto_installer::CharonIoError, main_index, util_index::{self, FileRecord, UtilIndex}};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    Missing(PathBuf),
+    Modified(PathBuf),
+    /// Path, expected mode, actual mode.
+    PermsChanged(PathBuf, u32, u32),
+    Unexpected(PathBuf),
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Drift::Missing(path) => write!(f, "Missing:\t{path:?}"),
+            Drift::Modified(path) => write!(f, "Modified:\t{path:?}"),
+            Drift::PermsChanged(path, expected, actual) => write!(f, "Permissions:\t{path:?} (expected {expected:o}, found {actual:o})"),
+            Drift::Unexpected(path) => write!(f, "Unexpected:\t{path:?}"),
+        };
+    }
+}
+
+pub fn verify_utils(mut utils: Vec<String>) -> Result<bool, CharonIoError> {
+    //! Returns true if every util matches its index.
+    //! If no utils are given, verifies every util in the main index.
+    let root_path = match get_path(MythosDir::Data, "charon") {
+        Some(path) => path,
+        None => {
+            printinfo!("Could not find any installed utilities");
+            return Ok(true);
+        }
+    };
+
+    if utils.is_empty() {
+        utils = main_index::load_main_index(false)?.keys().cloned().collect();
+    }
+
+    let mut is_clean = true;
+    for util in utils {
+        let path = root_path.join(format!("{util}.charon"));
+        if !path.exists() {
+            printerror!("{}", CharonIoError::UnknownUtilName(Some(util)));
+            is_clean = false;
+            continue;
+        }
+
+        let drift = verify_index(&UtilIndex::load(&path)?);
+        if drift.is_empty() {
+            printinfo!("{util}: OK");
+            continue;
+        }
+
+        is_clean = false;
+        printwarn!("{util}: {} problem(s) found", drift.len());
+        for item in drift {
+            printinfo!("\t{item}");
+        }
+    }
+    return Ok(is_clean);
+}
+
+pub fn verify_index(index: &UtilIndex) -> Vec<Drift> {
+    let mut output: Vec<Drift> = index.files.iter().filter_map(verify_file).collect();
+    output.append(&mut find_unexpected(index));
+    return output;
+}
+
+pub fn verify_file(record: &FileRecord) -> Option<Drift> {
+    let dest = record.dest.to_owned();
+    let metadata = match fs::symlink_metadata(&dest) {
+        Ok(metadata) => metadata,
+        Err(_) => return Some(Drift::Missing(dest))
+    };
+
+    if record.link {
+        // Links are not checksummed, only make sure they still point at target.
+        // Links point at the canonical target, which older records may not have stored.
+        return match fs::read_link(&dest) {
+            Ok(link) if link == record.target => None,
+            Ok(link) if fs::canonicalize(&record.target).is_ok_and(|target| target == link) => None,
+            _ => Some(Drift::Modified(dest))
+        };
+    }
+
+    // The user chose to keep their changes, see <dest>.charon-new for the installed version.
+    if !record.modified {
+        if let Some(expected) = &record.checksum {
+            if util_index::file_checksum(&dest).ok().as_ref() != Some(expected) {
+                return Some(Drift::Modified(dest));
+            }
+        }
+    }
+
+    if let Some(expected) = record.perms {
+        let actual = metadata.permissions().mode() & 0o7777;
+        if actual != expected {
+            return Some(Drift::PermsChanged(dest, expected, actual));
+        }
+    }
+    return None;
+}
+
+fn find_unexpected(index: &UtilIndex) -> Vec<Drift> {
+    //! Only directories charon created are checked, anything could be in pre-existing ones.
+    let created = index.created_dirs();
+    let mut output = Vec::new();
+
+    for dir in &created {
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if index.get_file(&path).is_none() && !created.contains(&path) {
+                output.push(Drift::Unexpected(path));
+            }
+        }
+    }
+    output.sort_by_key(|drift| drift.to_string());
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util_index::DirRecord;
+    use super::*;
+
+    fn record(dest: &str) -> FileRecord {
+        let dest = PathBuf::from(dest);
+        let mut record = FileRecord::from_dest(dest.to_owned());
+        record.checksum = util_index::file_checksum(&dest).ok();
+        record.perms = fs::metadata(&dest).ok().map(|metadata| metadata.permissions().mode() & 0o7777);
+        return record;
+    }
+
+    #[test]
+    fn find_drift() {
+        let mut index = UtilIndex::new();
+        index.dirs.push(DirRecord { path: PathBuf::from("tests/verify/created"), existed: false });
+
+        index.files.push(record("tests/verify/created/same.txt"));
+        index.files.push(record("tests/verify/created/dne.txt"));
+
+        let mut modified = record("tests/verify/created/modified.txt");
+        modified.checksum = Some(util_index::checksum(b"original\n"));
+        index.files.push(modified);
+
+        let mut perms = record("tests/verify/created/perms.txt");
+        let actual = perms.perms.unwrap();
+        perms.perms = Some(actual ^ 0o001);
+        index.files.push(perms);
+
+        assert_eq!(verify_index(&index), vec![
+            Drift::Missing(PathBuf::from("tests/verify/created/dne.txt")),
+            Drift::Modified(PathBuf::from("tests/verify/created/modified.txt")),
+            Drift::PermsChanged(PathBuf::from("tests/verify/created/perms.txt"), actual ^ 0o001, actual),
+            Drift::Unexpected(PathBuf::from("tests/verify/created/extra.txt")),
+        ]);
+    }
+
+    #[test]
+    fn link_through_symlinked_dir() {
+        // alias is a symlink to real, links are created to the canonical target.
+        let dest = PathBuf::from("tests/verify/links/installed");
+        let _ = fs::remove_file(&dest);
+        std::os::unix::fs::symlink(fs::canonicalize("tests/verify/links/alias/target.txt").unwrap(), &dest).unwrap();
+
+        let mut link = FileRecord::from_dest(dest.to_owned());
+        link.target = PathBuf::from("tests/verify/links/alias/target.txt");
+        link.link = true;
+        let res = verify_file(&link);
+        fs::remove_file(&dest).unwrap();
+        assert_eq!(res, None);
+    }
+}
@@ -0,0 +1,309 @@
+/*!
+ * Version numbers, as written in the info section of charon files.
+ * Follows SemVer 2.0, but tolerates a leading 'v', any number of numeric components (1.2, 0.0.2.1)
+ * and date based versions (2024.01.15, 2024-01-15).
+ */
+
+use std::{cmp::Ordering, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// Numeric components, e.g. [1, 2, 0] for 1.2.0.
+    pub numbers: Vec<u64>,
+    /// Pre-release identifiers, e.g. ["rc", 1] for 1.2.0-rc.1
+    pub pre: Vec<Identifier>,
+    /// Build metadata. Ignored when comparing versions.
+    pub build: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identifier {
+    Numeric(u64),
+    Alpha(String),
+}
+
+/**
+ * A comma separated list of comparators, all of which must match. e.g. ">=1.2, <2".
+ * A version without an operator is treated like ^version.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    pub comparators: Vec<Comparator>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparator {
+    pub op: Op,
+    /// May have less than 3 components, e.g. =1.2 matches any 1.2.x
+    pub version: Version,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    /// Same major and minor version.
+    Tilde,
+    /// Same leftmost non-zero component.
+    Caret,
+    /// "*", matches anything.
+    Any,
+}
+
+impl Version {
+    pub fn parse(val: &str) -> Option<Version> {
+        let val = val.trim();
+        let val = val.strip_prefix(['v', 'V']).unwrap_or(val);
+
+        let (val, build) = match val.split_once('+') {
+            Some((val, build)) if is_valid_identifiers(build) => (val, Some(build.to_string())),
+            Some(_) => return None,
+            None => (val, None),
+        };
+
+        // 2024-01-15 is a date, not version 2024 with pre-release 01-15.
+        let (core, pre) = match val.split_once('-') {
+            Some((core, rest)) if !core.contains('.') && rest.chars().all(|c| c.is_ascii_digit() || c == '-') => {
+                (val.replace('-', "."), None)
+            },
+            Some((core, pre)) => (core.to_string(), Some(pre)),
+            None => (val.to_string(), None),
+        };
+
+        let numbers = core
+            .split('.')
+            .map(|num| if num.is_empty() { None } else { num.parse::<u64>().ok() })
+            .collect::<Option<Vec<u64>>>()?;
+
+        let pre = match pre {
+            Some(pre) if is_valid_identifiers(pre) => pre.split('.').map(Identifier::parse).collect(),
+            Some(_) => return None,
+            None => Vec::new(),
+        };
+
+        return Some(Version { numbers, pre, build });
+    }
+}
+
+impl VersionReq {
+    pub fn parse(val: &str) -> Option<VersionReq> {
+        let comparators = val
+            .split(',')
+            .map(Comparator::parse)
+            .collect::<Option<Vec<Comparator>>>()?;
+        return Some(VersionReq { comparators });
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        return self.comparators.iter().all(|comparator| comparator.matches(version));
+    }
+}
+
+impl Comparator {
+    fn parse(val: &str) -> Option<Comparator> {
+        let val = val.trim();
+        if val == "*" {
+            return Some(Comparator { op: Op::Any, version: Version::parse("0")? });
+        }
+
+        // Longest operators first, so ">=" is not read as ">".
+        let ops = [(">=", Op::GreaterEq), ("<=", Op::LessEq), (">", Op::Greater), ("<", Op::Less),
+            ("=", Op::Exact), ("~", Op::Tilde), ("^", Op::Caret)];
+        let (op, version) = ops
+            .iter()
+            .find_map(|(prefix, op)| val.strip_prefix(prefix).map(|rest| (*op, rest)))
+            .unwrap_or((Op::Caret, val));
+
+        return Some(Comparator { op, version: Version::parse(version)? });
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        let req = &self.version;
+        return match self.op {
+            Op::Any => true,
+            Op::Exact => req.pre == version.pre && req.numbers
+                .iter()
+                .enumerate()
+                .all(|(i, num)| version.numbers.get(i).unwrap_or(&0) == num),
+            Op::Greater => version > req,
+            Op::GreaterEq => version >= req,
+            Op::Less => version < req,
+            Op::LessEq => version <= req,
+            Op::Tilde => version >= req && version < &req.bump(if req.numbers.len() > 1 { 1 } else { 0 }),
+            Op::Caret => {
+                let i = req.numbers
+                    .iter()
+                    .position(|num| *num != 0)
+                    .unwrap_or(req.numbers.len().saturating_sub(1));
+                version >= req && version < &req.bump(i)
+            },
+        };
+    }
+}
+
+impl Version {
+    fn bump(&self, i: usize) -> Version {
+        //! Smallest version with component i incremented, e.g. bump(1) of 1.2.3 is 1.3.0-0.
+        //! The -0 pre-release keeps pre-releases of the next version out of range.
+        let mut numbers: Vec<u64> = self.numbers.iter().take(i + 1).cloned().collect();
+        if let Some(last) = numbers.last_mut() {
+            *last += 1;
+        }
+        return Version { numbers, pre: vec![Identifier::Numeric(0)], build: None };
+    }
+}
+
+impl Identifier {
+    fn parse(val: &str) -> Identifier {
+        return match val.parse::<u64>() {
+            Ok(num) => Identifier::Numeric(num),
+            Err(_) => Identifier::Alpha(val.to_string()),
+        };
+    }
+}
+
+fn is_valid_identifiers(val: &str) -> bool {
+    //! Dot separated, non-empty, alphanumerics and hyphens only.
+    return val.split('.').all(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        //! Missing components count as 0, so 1.2 == 1.2.0.
+        //! A pre-release is older than the release it precedes.
+        let len = self.numbers.len().max(other.numbers.len());
+        for i in 0..len {
+            let a = self.numbers.get(i).unwrap_or(&0);
+            let b = other.numbers.get(i).unwrap_or(&0);
+            match a.cmp(b) {
+                Ordering::Equal => continue,
+                ord => return ord,
+            }
+        }
+
+        return match (self.pre.is_empty(), other.pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.pre.cmp(&other.pre),
+        };
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        //! Numeric identifiers always have lower precedence than alphanumeric ones.
+        return match (self, other) {
+            (Identifier::Numeric(a), Identifier::Numeric(b)) => a.cmp(b),
+            (Identifier::Numeric(_), Identifier::Alpha(_)) => Ordering::Less,
+            (Identifier::Alpha(_), Identifier::Numeric(_)) => Ordering::Greater,
+            (Identifier::Alpha(a), Identifier::Alpha(b)) => a.cmp(b),
+        };
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let numbers: Vec<String> = self.numbers.iter().map(|num| num.to_string()).collect();
+        write!(f, "{}", numbers.join("."))?;
+        if !self.pre.is_empty() {
+            let pre: Vec<String> = self.pre.iter().map(|id| id.to_string()).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if let Some(build) = &self.build {
+            write!(f, "+{build}")?;
+        }
+        return Ok(());
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Identifier::Numeric(num) => write!(f, "{num}"),
+            Identifier::Alpha(val) => write!(f, "{val}"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(val: &str) -> Version {
+        return Version::parse(val).unwrap();
+    }
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(parse("1.2.3").numbers, vec![1, 2, 3]);
+        assert_eq!(parse("v1.2").numbers, vec![1, 2]);
+        assert_eq!(parse("0.0.2.1").numbers, vec![0, 0, 2, 1]);
+        assert_eq!(parse("2024-01-15").numbers, vec![2024, 1, 15]);
+        assert_eq!(parse("1.2.0-rc.1").pre, vec![Identifier::Alpha("rc".into()), Identifier::Numeric(1)]);
+        assert_eq!(parse("1.2.0+build.5").build, Some("build.5".into()));
+        assert_eq!(parse("1.2.0-rc1+abc").to_string(), "1.2.0-rc1+abc");
+
+        assert!(Version::parse("").is_none());
+        assert!(Version::parse("1..2").is_none());
+        assert!(Version::parse("one.two").is_none());
+        assert!(Version::parse("1.2.0-").is_none());
+        assert!(Version::parse("1.2.0+").is_none());
+    }
+
+    #[test]
+    fn version_ordering() {
+        // Precedence example from the SemVer 2.0 spec.
+        let versions = [
+            "1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta",
+            "1.0.0-beta.2", "1.0.0-beta.11", "1.0.0-rc.1", "1.0.0",
+        ];
+        for pair in versions.windows(2) {
+            assert!(parse(pair[0]) < parse(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+
+        assert_eq!(parse("1.2").cmp(&parse("1.2.0")), Ordering::Equal);
+        assert_eq!(parse("1.2.0+a").cmp(&parse("1.2.0+b")), Ordering::Equal);
+        assert!(parse("v1.10") > parse("1.9"));
+        assert!(parse("2024.01.15") < parse("2024-02-01"));
+    }
+
+    #[test]
+    fn version_requirements() {
+        let matches = |req: &str, version: &str| VersionReq::parse(req).unwrap().matches(&parse(version));
+
+        assert!(matches("^1.2", "1.9.0"));
+        assert!(!matches("^1.2", "2.0.0"));
+        assert!(!matches("^1.2", "2.0.0-rc.1"));
+        assert!(!matches("^1.2", "1.1.9"));
+        assert!(matches("^0.2.3", "0.2.9"));
+        assert!(!matches("^0.2.3", "0.3.0"));
+        assert!(matches("1.2", "1.4.0"));
+        assert!(matches("~1.2.3", "1.2.9"));
+        assert!(!matches("~1.2.3", "1.3.0"));
+        assert!(matches("=1.2", "1.2.7"));
+        assert!(!matches("=1.2.3", "1.2.4"));
+        assert!(matches(">=1.0, <2.0", "1.5.0"));
+        assert!(!matches(">=1.0, <2.0", "2.0.0"));
+        assert!(matches("*", "9.9.9"));
+
+        assert!(VersionReq::parse(">=").is_none());
+        assert!(VersionReq::parse("1.0,").is_none());
+        assert!(VersionReq::parse("latest").is_none());
+    }
+}